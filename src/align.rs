@@ -0,0 +1,145 @@
+//! Mapping between the vocabs of two models.
+//!
+//! When a model switches to a new vocab, the embedding rows of the new tokens can be initialized
+//! from the old model instead of from scratch: a new token whose bytes exist in the old vocab
+//! reuses that row, and any other token can start from the rows of the old tokens that
+//! concatenate to it (e.g. their mean).
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::tokenizers::basic::Tokenizer;
+use crate::util::{csv_field, render_token};
+
+/// Maps every id of `new` to the id of the `old` token with the same bytes.
+///
+/// The result is indexed by new id. Ids that are not used by `new`, and tokens without an exact
+/// match in `old`, map to `None`. Special tokens match special tokens with the same text.
+pub fn alignment(old: &Tokenizer, new: &Tokenizer) -> Vec<Option<u32>> {
+    composite_alignment(old, new)
+        .into_iter()
+        .map(|old_ids| match old_ids.as_slice() {
+            [old_id] => Some(*old_id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Maps every id of `new` to the `old` ids whose bytes concatenate to the new token.
+///
+/// Tokens with an exact match map to a single id. Other tokens map to the old model's encoding
+/// of their bytes, which always exists because both vocabs contain every byte. Unused ids, and
+/// special tokens that the old model does not have, map to an empty list.
+pub fn composite_alignment(old: &Tokenizer, new: &Tokenizer) -> Vec<Vec<u32>> {
+    let old_ids: HashMap<&[u8], u32> = {
+        let mut ids: Vec<(&u32, &Vec<u8>)> = old.vocab.iter().collect();
+        // Keep the lowest id when the same bytes were learned twice.
+        ids.sort_unstable_by_key(|&(&id, _)| std::cmp::Reverse(id));
+        ids.into_iter().map(|(&id, token)| (token.as_slice(), id)).collect()
+    };
+
+    let mut aligned = vec![Vec::new(); vocab_len(new)];
+    for (&id, token) in &new.vocab {
+        aligned[id as usize] = match old_ids.get(token.as_slice()) {
+            Some(&old_id) => vec![old_id],
+            None => old.encode_bytes(token),
+        };
+    }
+    for (token, &id) in &new.special_tokens {
+        if let Some(&old_id) = old.special_tokens.get(token) {
+            aligned[id as usize] = vec![old_id];
+        }
+    }
+    aligned
+}
+
+/// Writes the composite alignment of `new` against `old` as CSV with the columns `new_id`,
+/// `token` (rendered like the `.vocab` file), `exact` and `old_ids` (space separated).
+pub fn write_alignment_csv(path: &str, old: &Tokenizer, new: &Tokenizer) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "new_id,token,exact,old_ids")?;
+    for (id, old_ids) in composite_alignment(old, new).iter().enumerate() {
+        let id = id as u32;
+        let (token, exact) = match (new.vocab.get(&id), new.inverse_special_tokens.get(&id)) {
+            (Some(token), _) => {
+                let exact = old_ids.len() == 1 && old.vocab.get(&old_ids[0]) == Some(token);
+                (render_token(token), exact)
+            }
+            (None, Some(special)) => (special.clone(), !old_ids.is_empty()),
+            (None, None) => continue,
+        };
+        let old_ids: Vec<String> = old_ids.iter().map(|id| id.to_string()).collect();
+        writeln!(file, "{},{},{},{}", id, csv_field(&token), exact, old_ids.join(" "))?;
+    }
+    file.flush()
+}
+
+fn vocab_len(tokenizer: &Tokenizer) -> usize {
+    let max_id =
+        tokenizer.vocab.keys().chain(tokenizer.inverse_special_tokens.keys()).copied().max();
+    max_id.map_or(0, |id| id as usize + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenizerTrait;
+    use tempfile::tempdir;
+
+    fn tokenizer(text: &str, vocab_size: u32) -> Tokenizer {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train(text, vocab_size, false);
+        tokenizer
+    }
+
+    #[test]
+    fn test_alignment() {
+        let old = tokenizer("aaab", 257);
+        let new = tokenizer("abababab", 258);
+        assert_eq!(old.vocab[&256], b"aa");
+        assert_eq!(new.vocab[&256], b"ab");
+        assert_eq!(new.vocab[&257], b"abab");
+
+        let aligned = alignment(&old, &new);
+        assert_eq!(aligned.len(), 258);
+        assert_eq!(aligned[b'a' as usize], Some(b'a' as u32));
+        assert_eq!(aligned[256], None);
+        assert_eq!(aligned[257], None);
+
+        let composite = composite_alignment(&old, &new);
+        assert_eq!(composite[256], vec![97, 98]);
+        assert_eq!(composite[257], vec![97, 98, 97, 98]);
+        assert_eq!(alignment(&new, &new)[257], Some(257));
+    }
+
+    #[test]
+    fn test_special_tokens() {
+        let mut old = tokenizer("", 256);
+        let mut new = tokenizer("", 256);
+        old.register_special_tokens(HashMap::from([("<|endoftext|>".to_string(), 300)]));
+        new.register_special_tokens(HashMap::from([
+            ("<|endoftext|>".to_string(), 256),
+            ("<|pad|>".to_string(), 257),
+        ]));
+        let aligned = composite_alignment(&old, &new);
+        assert_eq!(aligned[256], vec![300]);
+        assert!(aligned[257].is_empty());
+    }
+
+    #[test]
+    fn test_write_alignment_csv() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("alignment.csv");
+        let old = tokenizer("a,a,a,", 257);
+        let new = tokenizer("a,a,a,b", 257);
+        write_alignment_csv(path.to_str().unwrap(), &old, &new)?;
+
+        let csv = std::fs::read_to_string(path)?;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "new_id,token,exact,old_ids");
+        assert_eq!(lines[1], "0,\\x00,true,0");
+        assert_eq!(lines[b',' as usize + 1], "44,\",\",true,44");
+        assert_eq!(lines[257], "256,\"a,\",true,256");
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+//! Training configuration files.
+//!
+//! A training run can be described by a small TOML file so that it can be reviewed and
+//! reproduced later:
+//!
+//! ```toml
+//! tokenizer = "regex"
+//! inputs = ["data/taylorswift.txt", "data/extra.txt"]
+//! weights = [1, 3]
+//! vocab_size = 1024
+//! normalizer = "none"
+//! special_tokens = ["<|endoftext|>"]
+//! output = "models/taylor-1k"
+//! max_token_len = 16
+//! whitespace_guard = false
+//! blocklist = ["password"]
+//! blocklist_patterns = ['^[0-9]{4,}$']
+//! min_gain = 0.0001
+//! ```
+//!
+//! All keys live at the top level; tables are rejected as unknown keys.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::normalizer::Normalizer;
+use crate::tokenizers::regex::GPT4_SPLIT_PATTERN;
+use crate::train::{Blocked, TrainOptions};
+use regex::bytes::Regex;
+use toml::{Table, Value};
+
+/// A fully resolved description of a training run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainConfig {
+    /// Tokenizer algorithm, either `basic` or `regex`.
+    pub tokenizer: String,
+    /// Training input files.
+    pub inputs: Vec<PathBuf>,
+    /// How many times each input is repeated in the training corpus, parallel to `inputs`.
+    pub weights: Vec<u32>,
+    /// Size of the vocabulary, including the 256 byte tokens.
+    pub vocab_size: u32,
+    /// Split pattern used by the regex tokenizer. Empty for the basic tokenizer.
+    pub pattern: String,
+    /// Normalizer applied to every input before training.
+    pub normalizer: Normalizer,
+    /// Special tokens registered after training, with ids starting at `vocab_size`.
+    pub special_tokens: Vec<String>,
+    /// File prefix of the trained model.
+    pub output: Option<PathBuf>,
+    /// Longest token, in bytes, that training may create.
+    pub max_token_len: Option<usize>,
+    /// Forbid merges that cross whitespace boundaries, see [`TrainOptions::whitespace_guard`].
+    pub whitespace_guard: bool,
+    /// Byte sequences that no learned token may contain.
+    pub blocklist: Vec<String>,
+    /// Regexes that no learned token may match, see [`Blocked::Pattern`].
+    pub blocklist_patterns: Vec<String>,
+    /// Stop once a merge gains less than this fraction, see [`TrainOptions::min_gain`].
+    pub min_gain: Option<f64>,
+}
+
+impl TrainConfig {
+    /// Reads and resolves the config file at `path`.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Self::from_toml_str(&contents)
+    }
+
+    /// Parses a config from TOML and fills in defaults for every optional key.
+    pub fn from_toml_str(contents: &str) -> io::Result<Self> {
+        let mut tokenizer = "regex".to_string();
+        let mut inputs = None;
+        let mut weights = None;
+        let mut vocab_size = None;
+        let mut pattern = None;
+        let mut normalizer = Normalizer::None;
+        let mut special_tokens = Vec::new();
+        let mut output = None;
+        let mut max_token_len = None;
+        let mut whitespace_guard = false;
+        let mut blocklist = Vec::new();
+        let mut blocklist_patterns = Vec::new();
+        let mut min_gain = None;
+
+        let table: Table =
+            contents.parse().map_err(|e| invalid(format!("Invalid config: {}", e)))?;
+        for (key, value) in table {
+            match key.as_str() {
+                "tokenizer" => tokenizer = value.into_string(&key)?,
+                "inputs" => {
+                    inputs = Some(value.into_string_array(&key)?.into_iter().map(PathBuf::from))
+                }
+                "weights" => {
+                    weights = Some(
+                        value
+                            .into_array(&key)?
+                            .into_iter()
+                            .map(|weight| weight.into_u32(&key))
+                            .collect::<io::Result<Vec<u32>>>()?,
+                    )
+                }
+                "vocab_size" => vocab_size = Some(value.into_u32(&key)?),
+                "pattern" => pattern = Some(value.into_string(&key)?),
+                "normalizer" => normalizer = value.into_string(&key)?.parse().map_err(invalid)?,
+                "special_tokens" => special_tokens = value.into_string_array(&key)?,
+                "output" => output = Some(PathBuf::from(value.into_string(&key)?)),
+                "max_token_len" => max_token_len = Some(value.into_u32(&key)? as usize),
+                "whitespace_guard" => whitespace_guard = value.into_bool(&key)?,
+                "blocklist" => blocklist = value.into_string_array(&key)?,
+                "blocklist_patterns" => blocklist_patterns = value.into_string_array(&key)?,
+                "min_gain" => min_gain = Some(value.into_f64(&key)?),
+                _ => return Err(invalid(format!("Unknown config key '{}'", key))),
+            }
+        }
+
+        let inputs: Vec<PathBuf> =
+            inputs.ok_or_else(|| invalid("Missing required key 'inputs'"))?.collect();
+        if inputs.is_empty() {
+            return Err(invalid("'inputs' must list at least one file"));
+        }
+        let weights = weights.unwrap_or_else(|| vec![1; inputs.len()]);
+        if weights.len() != inputs.len() {
+            return Err(invalid(format!(
+                "'weights' has {} entries but 'inputs' has {}",
+                weights.len(),
+                inputs.len()
+            )));
+        }
+        let vocab_size = vocab_size.ok_or_else(|| invalid("Missing required key 'vocab_size'"))?;
+        if vocab_size < 256 {
+            return Err(invalid("'vocab_size' must be at least 256"));
+        }
+        let pattern = match tokenizer.as_str() {
+            "basic" => {
+                if pattern.is_some() {
+                    return Err(invalid("The basic tokenizer does not take a 'pattern'"));
+                }
+                String::new()
+            }
+            "regex" => pattern.unwrap_or_else(|| GPT4_SPLIT_PATTERN.to_string()),
+            _ => return Err(invalid(format!("Unknown tokenizer '{}'", tokenizer))),
+        };
+
+        TrainConfig {
+            tokenizer,
+            inputs,
+            weights,
+            vocab_size,
+            pattern,
+            normalizer,
+            special_tokens,
+            output,
+            max_token_len,
+            whitespace_guard,
+            blocklist,
+            blocklist_patterns,
+            min_gain,
+        }
+        .validated()
+    }
+
+    /// Checks the parts of the config that can only be checked by compiling them.
+    fn validated(self) -> io::Result<Self> {
+        if self.min_gain.is_some_and(|min_gain| !(0.0..=1.0).contains(&min_gain)) {
+            return Err(invalid("'min_gain' must be between 0 and 1"));
+        }
+        self.train_options(false)?;
+        Ok(self)
+    }
+
+    /// Returns the options to train with.
+    pub fn train_options(&self, verbose: bool) -> io::Result<TrainOptions> {
+        let mut blocklist: Vec<Blocked> = self
+            .blocklist
+            .iter()
+            .map(|blocked| Blocked::Bytes(blocked.as_bytes().to_vec()))
+            .collect();
+        for pattern in &self.blocklist_patterns {
+            let pattern = Regex::new(pattern)
+                .map_err(|e| invalid(format!("Invalid blocklist pattern: {}", e)))?;
+            blocklist.push(Blocked::Pattern(pattern));
+        }
+        Ok(TrainOptions {
+            vocab_size: self.vocab_size,
+            verbose,
+            max_token_len: self.max_token_len,
+            whitespace_guard: self.whitespace_guard,
+            blocklist,
+            min_gain: self.min_gain,
+            record_curve: false,
+        })
+    }
+
+    /// Serializes the config back to TOML, with every default spelled out.
+    pub fn to_toml_string(&self) -> String {
+        let mut table = Table::new();
+        table.insert("tokenizer".into(), Value::String(self.tokenizer.clone()));
+        table.insert(
+            "inputs".into(),
+            string_array(self.inputs.iter().map(|input| input.to_string_lossy())),
+        );
+        table.insert(
+            "weights".into(),
+            Value::Array(
+                self.weights.iter().map(|&weight| Value::Integer(weight.into())).collect(),
+            ),
+        );
+        table.insert("vocab_size".into(), Value::Integer(self.vocab_size.into()));
+        if !self.pattern.is_empty() {
+            table.insert("pattern".into(), Value::String(self.pattern.clone()));
+        }
+        table.insert("normalizer".into(), Value::String(self.normalizer.as_str().to_string()));
+        table.insert("special_tokens".into(), string_array(&self.special_tokens));
+        if let Some(output) = &self.output {
+            table.insert("output".into(), Value::String(output.to_string_lossy().into_owned()));
+        }
+        if let Some(max_token_len) = self.max_token_len {
+            table.insert("max_token_len".into(), Value::Integer(max_token_len as i64));
+        }
+        table.insert("whitespace_guard".into(), Value::Boolean(self.whitespace_guard));
+        table.insert("blocklist".into(), string_array(&self.blocklist));
+        table.insert("blocklist_patterns".into(), string_array(&self.blocklist_patterns));
+        if let Some(min_gain) = self.min_gain {
+            table.insert("min_gain".into(), Value::Float(min_gain));
+        }
+        table.to_string()
+    }
+
+    /// Reads every input, normalizes it and repeats it according to its weight.
+    pub fn load_corpus(&self) -> io::Result<String> {
+        let mut corpus = String::new();
+        for (input, &weight) in self.inputs.iter().zip(&self.weights) {
+            let contents = fs::read_to_string(input).map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to read {}: {}", input.display(), e))
+            })?;
+            let normalized = self.normalizer.apply(&contents);
+            for _ in 0..weight {
+                corpus.push_str(&normalized);
+            }
+        }
+        Ok(corpus)
+    }
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Accessors that turn TOML values into config fields, naming the key in errors.
+trait ConfigValue: Sized {
+    fn into_string(self, key: &str) -> io::Result<String>;
+    fn into_u32(self, key: &str) -> io::Result<u32>;
+    fn into_f64(self, key: &str) -> io::Result<f64>;
+    fn into_bool(self, key: &str) -> io::Result<bool>;
+    fn into_array(self, key: &str) -> io::Result<Vec<Self>>;
+
+    fn into_string_array(self, key: &str) -> io::Result<Vec<String>> {
+        self.into_array(key)?.into_iter().map(|value| value.into_string(key)).collect()
+    }
+}
+
+impl ConfigValue for Value {
+    fn into_string(self, key: &str) -> io::Result<String> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err(invalid(format!("'{}' must be a string", key))),
+        }
+    }
+
+    fn into_u32(self, key: &str) -> io::Result<u32> {
+        match self {
+            Value::Integer(n) => u32::try_from(n)
+                .map_err(|_| invalid(format!("'{}' must be a non-negative 32-bit integer", key))),
+            _ => Err(invalid(format!("'{}' must be an integer", key))),
+        }
+    }
+
+    fn into_f64(self, key: &str) -> io::Result<f64> {
+        match self {
+            Value::Float(x) => Ok(x),
+            Value::Integer(n) => Ok(n as f64),
+            _ => Err(invalid(format!("'{}' must be a number", key))),
+        }
+    }
+
+    fn into_bool(self, key: &str) -> io::Result<bool> {
+        match self {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(invalid(format!("'{}' must be a boolean", key))),
+        }
+    }
+
+    fn into_array(self, key: &str) -> io::Result<Vec<Value>> {
+        match self {
+            Value::Array(values) => Ok(values),
+            _ => Err(invalid(format!("'{}' must be an array", key))),
+        }
+    }
+}
+
+/// Converts strings into a TOML array value.
+fn string_array<S: ToString>(items: impl IntoIterator<Item = S>) -> Value {
+    Value::Array(items.into_iter().map(|item| Value::String(item.to_string())).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_defaults() {
+        let config = TrainConfig::from_toml_str(
+            r#"
+            # a comment
+            inputs = ["data/taylorswift.txt"]
+            vocab_size = 512
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.tokenizer, "regex");
+        assert_eq!(config.inputs, vec![PathBuf::from("data/taylorswift.txt")]);
+        assert_eq!(config.weights, vec![1]);
+        assert_eq!(config.pattern, GPT4_SPLIT_PATTERN);
+        assert_eq!(config.normalizer, Normalizer::None);
+        assert!(config.special_tokens.is_empty());
+        assert_eq!(config.output, None);
+        assert_eq!(config.max_token_len, None);
+        assert!(!config.whitespace_guard);
+    }
+
+    #[test]
+    fn test_parse_full() {
+        let config = TrainConfig::from_toml_str(
+            r#"
+            tokenizer = "regex"
+            inputs = [
+                "a.txt",
+                "b.txt", # trailing comma is fine
+            ]
+            weights = [1, 3]
+            vocab_size = 1_024
+            pattern = '\w+|\s+'
+            normalizer = "lowercase"
+            special_tokens = ["<|endoftext|>", "tab\there"]
+            output = "models/my-vocab"
+            max_token_len = 12
+            whitespace_guard = true
+            blocklist = ["secret"]
+            blocklist_patterns = ['^\d{4}$']
+            min_gain = 0.000_5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.weights, vec![1, 3]);
+        assert_eq!(config.vocab_size, 1024);
+        assert_eq!(config.pattern, r"\w+|\s+");
+        assert_eq!(config.normalizer, Normalizer::Lowercase);
+        assert_eq!(config.special_tokens, vec!["<|endoftext|>", "tab\there"]);
+        assert_eq!(config.output, Some(PathBuf::from("models/my-vocab")));
+        assert_eq!(config.max_token_len, Some(12));
+        assert!(config.whitespace_guard);
+        let options = config.train_options(false).unwrap();
+        assert_eq!(options.vocab_size, 1024);
+        assert_eq!(options.max_token_len, Some(12));
+        assert!(options.whitespace_guard);
+        assert_eq!(options.min_gain, Some(0.0005));
+        assert_eq!(options.blocklist.len(), 2);
+        assert!(!options.allows(b"secrets"));
+        assert!(!options.allows(b"2024"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let config = TrainConfig::from_toml_str(
+            r#"
+            inputs = ["a.txt"]
+            vocab_size = 300
+            special_tokens = ["<|endoftext|>"]
+            output = "out/model"
+            max_token_len = 8
+            whitespace_guard = true
+            blocklist = ["a\"b"]
+            blocklist_patterns = ['x+']
+            min_gain = 1e-4
+            "#,
+        )
+        .unwrap();
+        let reparsed = TrainConfig::from_toml_str(&config.to_toml_string()).unwrap();
+        assert_eq!(config, reparsed);
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        let cases = [
+            "vocab_size = 512",
+            "inputs = [\"a.txt\"]",
+            "inputs = [\"a.txt\"]\nvocab_size = 100",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\nweights = [1, 2]",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\nunknown = 1",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\nvocab_size = 600",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\ntokenizer = \"basic\"\npattern = \"x\"",
+            "[table]\ninputs = [\"a.txt\"]",
+            "inputs = [\"a.txt\"\nvocab_size = 512",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\nwhitespace_guard = 1",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\nblocklist_patterns = [\"(\"]",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\nmin_gain = 1.5",
+            "inputs = [\"a.txt\"]\nvocab_size = 512\nmin_gain = 1.2.3",
+        ];
+        for case in cases {
+            assert!(TrainConfig::from_toml_str(case).is_err(), "expected error for {:?}", case);
+        }
+    }
+
+    #[test]
+    fn test_load_corpus() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let a = temp_dir.path().join("a.txt");
+        let b = temp_dir.path().join("b.txt");
+        File::create(&a)?.write_all(b"Hello ")?;
+        File::create(&b)?.write_all(b"World")?;
+
+        let config = TrainConfig {
+            tokenizer: "basic".to_string(),
+            inputs: vec![a, b],
+            weights: vec![1, 2],
+            vocab_size: 256,
+            pattern: String::new(),
+            normalizer: Normalizer::Lowercase,
+            special_tokens: Vec::new(),
+            output: None,
+            max_token_len: None,
+            whitespace_guard: false,
+            blocklist: Vec::new(),
+            blocklist_patterns: Vec::new(),
+            min_gain: None,
+        };
+        assert_eq!(config.load_corpus()?, "hello worldworld");
+        Ok(())
+    }
+}
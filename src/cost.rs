@@ -0,0 +1,190 @@
+//! Token counts and cost estimates for prompts sent to an LLM API.
+//!
+//! Prompt files are JSON Lines with one request per line. The input of a request is the text in
+//! its `prompt`, `input`, `system` and `messages` fields, where messages contribute their
+//! `content` (a string or a list of parts with a `text` field). A `completion`, `response` or
+//! `output` field counts as the output of the request; requests without one are assumed to
+//! produce a fixed number of output tokens. Only the text is counted: chat APIs add a few tokens
+//! of formatting per message, so real bills are slightly higher.
+use std::io;
+use std::ops::AddAssign;
+
+use crate::{EncodeScratch, TokenizerTrait};
+use serde_json::Value;
+
+const INPUT_FIELDS: [&str; 4] = ["system", "prompt", "input", "messages"];
+const OUTPUT_FIELDS: [&str; 3] = ["completion", "response", "output"];
+
+/// Prices in currency units per million tokens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+/// Token counts of a batch of requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub requests: usize,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+}
+
+impl Usage {
+    /// Returns the cost of the requests at the given prices.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::cost::{Pricing, Usage};
+    ///
+    /// let usage = Usage { requests: 2, input_tokens: 400_000, output_tokens: 100_000 };
+    /// let pricing = Pricing { input_per_million: 2.5, output_per_million: 10.0 };
+    /// assert_eq!(usage.cost(&pricing), 2.0);
+    /// ```
+    pub fn cost(&self, pricing: &Pricing) -> f64 {
+        (self.input_tokens as f64 * pricing.input_per_million
+            + self.output_tokens as f64 * pricing.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+/// Counts a single request whose prompt is `text`, expecting `output_tokens` tokens back.
+pub fn count_text<T: TokenizerTrait + ?Sized>(
+    tokenizer: &T,
+    text: &str,
+    output_tokens: usize,
+) -> Usage {
+    Usage { requests: 1, input_tokens: tokenizer.encode(text).len(), output_tokens }
+}
+
+/// Counts every request of a JSON Lines prompt file, see the [module documentation](self) for
+/// the fields that are read. Requests without an output field count as `output_tokens` output
+/// tokens. Blank lines are skipped.
+pub fn count_jsonl<T: TokenizerTrait + ?Sized>(
+    tokenizer: &T,
+    content: &str,
+    output_tokens: usize,
+) -> io::Result<Usage> {
+    let mut usage = Usage::default();
+    let mut scratch = EncodeScratch::new();
+    let mut ids = Vec::new();
+    let mut count_tokens = |texts: &[&str]| -> usize {
+        texts
+            .iter()
+            .map(|text| {
+                ids.clear();
+                tokenizer.encode_into(text, &mut scratch, &mut ids);
+                ids.len()
+            })
+            .sum()
+    };
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |message: String| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", i + 1, message))
+        };
+        let request: Value = serde_json::from_str(line).map_err(|e| invalid(e.to_string()))?;
+        if !request.is_object() {
+            return Err(invalid("expected a JSON object".to_string()));
+        }
+
+        let mut input = Vec::new();
+        for field in INPUT_FIELDS {
+            if let Some(value) = request.get(field) {
+                collect_text(value, &mut input);
+            }
+        }
+        if INPUT_FIELDS.iter().all(|field| request.get(field).is_none()) {
+            return Err(invalid(format!("expected one of the fields {}", INPUT_FIELDS.join(", "))));
+        }
+        usage.requests += 1;
+        usage.input_tokens += count_tokens(&input);
+
+        match OUTPUT_FIELDS.iter().find_map(|field| request.get(field)) {
+            Some(value) => {
+                let mut output = Vec::new();
+                collect_text(value, &mut output);
+                usage.output_tokens += count_tokens(&output);
+            }
+            None => usage.output_tokens += output_tokens,
+        }
+    }
+    Ok(usage)
+}
+
+/// Collects the strings of a prompt: plain strings, the items of arrays, and the `content` and
+/// `text` fields of objects (messages and message parts).
+fn collect_text<'a>(value: &'a Value, texts: &mut Vec<&'a str>) {
+    match value {
+        Value::String(text) => texts.push(text),
+        Value::Array(items) => items.iter().for_each(|item| collect_text(item, texts)),
+        Value::Object(fields) => {
+            for key in ["content", "text"] {
+                if let Some(value) = fields.get(key) {
+                    collect_text(value, texts);
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizers::regex::RegexTokenizer;
+
+    #[test]
+    fn test_count_jsonl_invalid() {
+        let tokenizer = RegexTokenizer::new();
+        let cases = [
+            r#"{"prompt": }"#,
+            r#"{"prompt": "a""#,
+            r#"{"prompt": "\ud83d"}"#,
+            r#"{"prompt":"\ud83d\u0041"}"#,
+            r#"{"prompt": "a"} x"#,
+        ];
+        for case in cases {
+            let error = count_jsonl(&tokenizer, case, 0).unwrap_err();
+            assert!(error.to_string().starts_with("line 1: "), "{}", case);
+        }
+    }
+
+    #[test]
+    fn test_count_jsonl() -> io::Result<()> {
+        let tokenizer = RegexTokenizer::new();
+        let content = r#"{"prompt": "hello world", "completion": "hi"}
+
+{"messages": [{"role": "system", "content": "be brief"}, {"role": "user", "content": [{"type": "text", "text": "why?"}]}]}
+"#;
+        let usage = count_jsonl(&tokenizer, content, 100)?;
+        // Untrained, so every byte of the text is a token.
+        assert_eq!(usage, Usage { requests: 2, input_tokens: 11 + 8 + 4, output_tokens: 2 + 100 });
+
+        let error =
+            count_jsonl(&tokenizer, "{\"prompt\": \"a\"}\n{\"text\": \"b\"}", 0).unwrap_err();
+        assert!(error.to_string().starts_with("line 2: expected one of the fields"));
+        assert!(count_jsonl(&tokenizer, "[\"a\"]", 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_cost() {
+        let mut usage = count_text(&RegexTokenizer::new(), "abc", 7);
+        usage += Usage { requests: 1, input_tokens: 1, output_tokens: 3 };
+        assert_eq!(usage, Usage { requests: 2, input_tokens: 4, output_tokens: 10 });
+        let pricing = Pricing { input_per_million: 1e6, output_per_million: 0.5e6 };
+        assert_eq!(usage.cost(&pricing), 9.0);
+    }
+}
@@ -0,0 +1,429 @@
+//! Analyses of how well a trained model serves a corpus.
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::tokenizers::basic::Tokenizer;
+use crate::tokenizers::regex::RegexTokenizer;
+use crate::train::TrainOptions;
+use crate::{EncodeScratch, TokenizerTrait};
+
+/// A coarse Unicode script bucket.
+///
+/// Whitespace, digits, punctuation, symbols and combining marks are `Common`; letters from
+/// scripts without a bucket of their own are `Other`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Bengali,
+    Tamil,
+    Thai,
+    Georgian,
+    Hangul,
+    Hiragana,
+    Katakana,
+    Han,
+    Emoji,
+    Common,
+    Other,
+}
+
+impl Script {
+    /// Returns the script bucket of `ch`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::eval::Script;
+    ///
+    /// assert_eq!(Script::of('a'), Script::Latin);
+    /// assert_eq!(Script::of('안'), Script::Hangul);
+    /// assert_eq!(Script::of('😉'), Script::Emoji);
+    /// assert_eq!(Script::of(' '), Script::Common);
+    /// ```
+    pub fn of(ch: char) -> Script {
+        match ch as u32 {
+            0x41..=0x5A | 0x61..=0x7A => Script::Latin,
+            0x00..=0x7F => Script::Common,
+            0xD7 | 0xF7 => Script::Common,
+            0xC0..=0x24F | 0x1E00..=0x1EFF | 0x2C60..=0x2C7F | 0xA720..=0xA7FF => Script::Latin,
+            0xFF21..=0xFF3A | 0xFF41..=0xFF5A => Script::Latin,
+            0x300..=0x36F => Script::Common,
+            0x370..=0x3FF | 0x1F00..=0x1FFF => Script::Greek,
+            0x400..=0x52F | 0x2DE0..=0x2DFF | 0xA640..=0xA69F => Script::Cyrillic,
+            0x530..=0x58F => Script::Armenian,
+            0x590..=0x5FF | 0xFB1D..=0xFB4F => Script::Hebrew,
+            0x600..=0x6FF | 0x750..=0x77F | 0x8A0..=0x8FF => Script::Arabic,
+            0xFB50..=0xFDFF | 0xFE70..=0xFEFF => Script::Arabic,
+            0x900..=0x97F => Script::Devanagari,
+            0x980..=0x9FF => Script::Bengali,
+            0xB80..=0xBFF => Script::Tamil,
+            0xE00..=0xE7F => Script::Thai,
+            0x10A0..=0x10FF => Script::Georgian,
+            0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Hangul,
+            0x3040..=0x309F => Script::Hiragana,
+            0x30A0..=0x30FF | 0x31F0..=0x31FF | 0xFF66..=0xFF9F => Script::Katakana,
+            0x2E80..=0x2FDF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF => Script::Han,
+            0x20000..=0x2FA1F => Script::Han,
+            0x1F000..=0x1FAFF | 0x2600..=0x27BF => Script::Emoji,
+            _ if ch.is_alphabetic() => Script::Other,
+            _ => Script::Common,
+        }
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // `pad` honours width and alignment, so reports can line up in columns.
+        f.pad(&format!("{:?}", self))
+    }
+}
+
+/// Compression of the text written in one script.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScriptCoverage {
+    /// UTF-8 bytes of the characters in this script.
+    pub bytes: usize,
+    /// Number of characters in this script.
+    pub chars: usize,
+    /// Number of tokens attributed to this script.
+    pub tokens: usize,
+}
+
+impl ScriptCoverage {
+    /// Returns the average number of bytes per token, higher is better.
+    pub fn bytes_per_token(&self) -> f64 {
+        if self.tokens == 0 {
+            return 0.0;
+        }
+        self.bytes as f64 / self.tokens as f64
+    }
+}
+
+/// Encodes `text` and reports, per script, how many bytes and tokens it accounts for.
+///
+/// The text is encoded as a whole, so the numbers match real usage. Every token is attributed to
+/// the script of the first non-`Common` character it overlaps, so " the" counts as Latin, and
+/// tokens made only of whitespace or punctuation count as `Common`. The result is sorted by
+/// bytes, largest first.
+pub fn script_coverage<T: TokenizerTrait + ?Sized>(
+    tokenizer: &T,
+    text: &str,
+) -> Vec<(Script, ScriptCoverage)> {
+    let mut byte_scripts = vec![Script::Common; text.len()];
+    let mut coverage: HashMap<Script, ScriptCoverage> = HashMap::new();
+    for (offset, ch) in text.char_indices() {
+        let script = Script::of(ch);
+        byte_scripts[offset..offset + ch.len_utf8()].fill(script);
+        let entry = coverage.entry(script).or_default();
+        entry.bytes += ch.len_utf8();
+        entry.chars += 1;
+    }
+
+    let vocab = &tokenizer.as_ref().vocab;
+    let mut offset = 0;
+    for id in tokenizer.encode(text) {
+        let len = vocab.get(&id).map_or(0, |token| token.len());
+        let end = (offset + len).min(text.len());
+        let script = byte_scripts[offset..end]
+            .iter()
+            .copied()
+            .find(|&script| script != Script::Common)
+            .unwrap_or(Script::Common);
+        coverage.entry(script).or_default().tokens += 1;
+        offset = end;
+    }
+
+    let mut coverage: Vec<(Script, ScriptCoverage)> = coverage.into_iter().collect();
+    coverage.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(&b.0)));
+    coverage
+}
+
+/// A document that did not survive an encode/decode roundtrip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoundtripFailure {
+    /// Position of the document in the corpus.
+    pub document: usize,
+    /// Byte offset of the first difference between the document and its decoding.
+    pub offset: usize,
+    /// Ids whose decoded bytes cover the offset.
+    pub ids: Vec<u32>,
+}
+
+impl fmt::Display for RoundtripFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "document {} differs at byte {} (ids {:?})", self.document, self.offset, self.ids)
+    }
+}
+
+/// Checks that every document of a corpus decodes back to itself.
+///
+/// Implemented for every tokenizer, including `dyn TokenizerTrait`, so it can gate a freshly
+/// trained or loaded model before it is promoted.
+pub trait VerifyRoundtrip {
+    /// Encodes and decodes every document and returns the ones that changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::eval::VerifyRoundtrip;
+    /// use rbpe::tokenizers::regex::RegexTokenizer;
+    /// use rbpe::TokenizerTrait;
+    ///
+    /// let mut tokenizer = RegexTokenizer::new();
+    /// tokenizer.train("hello world", 260, false);
+    /// assert!(tokenizer.verify_roundtrip(["hello", "안녕 😉"]).is_empty());
+    /// ```
+    fn verify_roundtrip<I>(&self, corpus: I) -> Vec<RoundtripFailure>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>;
+}
+
+impl<T: TokenizerTrait + ?Sized> VerifyRoundtrip for T {
+    fn verify_roundtrip<I>(&self, corpus: I) -> Vec<RoundtripFailure>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let tokenizer = self.as_ref();
+        let mut failures = Vec::new();
+        let mut scratch = EncodeScratch::new();
+        let mut ids = Vec::new();
+        let mut decoded = Vec::new();
+        for (document, text) in corpus.into_iter().enumerate() {
+            let text = text.as_ref();
+            ids.clear();
+            self.encode_into(text, &mut scratch, &mut ids);
+            // Compare raw bytes, so that tokens decoding to invalid UTF-8 are located exactly.
+            decoded.clear();
+            for &id in &ids {
+                decoded.extend_from_slice(tokenizer.token_bytes(id).unwrap_or_default());
+            }
+            if decoded == text.as_bytes() {
+                continue;
+            }
+            let offset = text
+                .bytes()
+                .zip(decoded.iter().copied())
+                .position(|(expected, actual)| expected != actual)
+                .unwrap_or_else(|| text.len().min(decoded.len()));
+            let ids = ids_covering(tokenizer, &ids, offset);
+            failures.push(RoundtripFailure { document, offset, ids });
+        }
+        failures
+    }
+}
+
+/// Returns the ids to blame for a difference at `offset`: ids that decode to nothing there, or
+/// else the id whose bytes cover the offset, or the last id when the decoding is too short.
+fn ids_covering(tokenizer: &Tokenizer, ids: &[u32], offset: usize) -> Vec<u32> {
+    let mut empty = Vec::new();
+    let mut covering = None;
+    let mut start = 0;
+    for &id in ids {
+        let len = match (tokenizer.vocab.get(&id), tokenizer.inverse_special_tokens.get(&id)) {
+            (Some(token), _) => token.len(),
+            (None, Some(special)) => special.len(),
+            (None, None) => 0,
+        };
+        if len == 0 && start == offset {
+            empty.push(id);
+        } else if covering.is_none() && start <= offset && offset < start + len {
+            covering = Some(id);
+        }
+        start += len;
+    }
+    if !empty.is_empty() {
+        return empty;
+    }
+    covering.or(ids.last().copied()).into_iter().collect()
+}
+
+/// A tokenizer trained with one candidate split pattern, see [`compare_patterns`].
+pub struct PatternTrial {
+    pub tokenizer: RegexTokenizer,
+    /// Time spent pre-tokenizing the corpus and learning the merges.
+    pub train_time: Duration,
+    /// Number of pre-tokens the pattern splits the corpus into.
+    pub chunks: u64,
+    /// Number of tokens the trained tokenizer encodes the corpus into.
+    pub tokens: usize,
+    /// UTF-8 bytes of the corpus matched by the pattern, the only ones that get encoded.
+    pub bytes: usize,
+    /// UTF-8 bytes of the corpus the pattern does not match, which are dropped when encoding.
+    pub uncovered: usize,
+}
+
+impl PatternTrial {
+    /// Returns the average number of matched bytes per token, higher is better. Check
+    /// [`PatternTrial::uncovered`] too: a pattern that skips part of the text scores well here
+    /// without encoding that part at all.
+    pub fn bytes_per_token(&self) -> f64 {
+        if self.tokens == 0 {
+            return 0.0;
+        }
+        self.bytes as f64 / self.tokens as f64
+    }
+}
+
+/// Trains a tokenizer on `text` with every split pattern in `patterns` and measures how well
+/// each compresses the same text, to compare pre-tokenization patterns. Fails on the first
+/// pattern that does not compile.
+///
+/// The compression is measured on the training text, which flatters every pattern alike; pass a
+/// vocab size much smaller than the corpus to keep the comparison meaningful.
+pub fn compare_patterns(
+    text: &str,
+    patterns: &[&str],
+    options: &TrainOptions,
+) -> Result<Vec<PatternTrial>, regex::Error> {
+    let mut trials = Vec::with_capacity(patterns.len());
+    for pattern in patterns {
+        let mut tokenizer = RegexTokenizer::with_pattern(pattern)?;
+        let start = Instant::now();
+        let counts = tokenizer.chunk_counts(text);
+        tokenizer.train_on_chunk_counts(&counts, options);
+        let train_time = start.elapsed();
+
+        let mut ids = Vec::new();
+        tokenizer.encode_into(text, &mut EncodeScratch::new(), &mut ids);
+        let bytes: usize = counts.iter().map(|(chunk, &count)| chunk.len() * count as usize).sum();
+        trials.push(PatternTrial {
+            tokenizer,
+            train_time,
+            chunks: counts.values().sum(),
+            tokens: ids.len(),
+            bytes,
+            uncovered: text.len() - bytes,
+        });
+    }
+    Ok(trials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizers::regex::RegexTokenizer;
+
+    #[test]
+    fn test_script_of() {
+        assert_eq!(Script::of('Z'), Script::Latin);
+        assert_eq!(Script::of('é'), Script::Latin);
+        assert_eq!(Script::of('7'), Script::Common);
+        assert_eq!(Script::of('×'), Script::Common);
+        assert_eq!(Script::of('ж'), Script::Cyrillic);
+        assert_eq!(Script::of('λ'), Script::Greek);
+        assert_eq!(Script::of('ש'), Script::Hebrew);
+        assert_eq!(Script::of('ع'), Script::Arabic);
+        assert_eq!(Script::of('ह'), Script::Devanagari);
+        assert_eq!(Script::of('ก'), Script::Thai);
+        assert_eq!(Script::of('中'), Script::Han);
+        assert_eq!(Script::of('ひ'), Script::Hiragana);
+        assert_eq!(Script::of('カ'), Script::Katakana);
+        assert_eq!(Script::of('☀'), Script::Emoji);
+        assert_eq!(Script::of('。'), Script::Common);
+        assert_eq!(Script::of('ሀ'), Script::Other);
+    }
+
+    #[test]
+    fn test_script_coverage_counts_every_token_once() {
+        let text = "hello world 안녕하세요 привет 😉!";
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.train("hello hello world world", 260, false);
+        let coverage = script_coverage(&tokenizer, text);
+
+        let total_tokens: usize = coverage.iter().map(|(_, c)| c.tokens).sum();
+        let total_bytes: usize = coverage.iter().map(|(_, c)| c.bytes).sum();
+        assert_eq!(total_tokens, tokenizer.encode(text).len());
+        assert_eq!(total_bytes, text.len());
+
+        let get = |script| &coverage.iter().find(|(s, _)| *s == script).unwrap().1;
+        assert_eq!(coverage[0].0, Script::Hangul);
+        assert_eq!(get(Script::Hangul).tokens, 15);
+        assert_eq!(get(Script::Cyrillic).chars, 6);
+        assert_eq!(get(Script::Emoji).tokens, 4);
+        assert!(get(Script::Latin).bytes_per_token() > get(Script::Hangul).bytes_per_token());
+    }
+
+    #[test]
+    fn test_script_coverage_of_empty_text() {
+        assert!(script_coverage(&Tokenizer::new(), "").is_empty());
+    }
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("hello hello hello", 258, false);
+        let corpus = ["hello", "say hello\n", "", "héllo 😉"];
+        assert!(tokenizer.verify_roundtrip(corpus).is_empty());
+
+        // Corrupt "hel" so that every document using it decodes wrongly.
+        tokenizer.vocab.insert(257, b"hal".to_vec());
+        let failures = tokenizer.verify_roundtrip(corpus);
+        assert_eq!(
+            failures,
+            vec![
+                RoundtripFailure { document: 0, offset: 1, ids: vec![257] },
+                RoundtripFailure { document: 1, offset: 5, ids: vec![257] },
+            ]
+        );
+        assert_eq!(failures[0].to_string(), "document 0 differs at byte 1 (ids [257])");
+    }
+
+    #[test]
+    fn test_verify_roundtrip_invalid_utf8() {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("hello hello hello", 258, false);
+        tokenizer.vocab.insert(257, b"h\xffl".to_vec());
+        let failures = tokenizer.verify_roundtrip(["say hello"]);
+        assert_eq!(failures, vec![RoundtripFailure { document: 0, offset: 5, ids: vec![257] }]);
+    }
+
+    #[test]
+    fn test_verify_roundtrip_through_dyn() {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("abab", 257, false);
+        tokenizer.vocab.remove(&256);
+        let model: Box<dyn TokenizerTrait> = Box::new(tokenizer);
+        let failures = model.verify_roundtrip(vec!["xabx".to_string()]);
+        assert_eq!(failures, vec![RoundtripFailure { document: 0, offset: 1, ids: vec![256] }]);
+    }
+
+    #[test]
+    fn test_compare_patterns() {
+        let text = "the cat and the hat, then the bath";
+        let patterns = [r"\w+|\W+", r"\s?\w+|[^\s\w]+|\s+"];
+        let trials = compare_patterns(text, &patterns, &TrainOptions::new(262)).unwrap();
+        assert_eq!(trials.len(), 2);
+        assert_eq!(trials[0].tokenizer.tokenizer().pattern, patterns[0]);
+        assert_eq!(trials[0].chunks, 15);
+        assert_eq!(trials[1].chunks, 9);
+        for trial in &trials {
+            assert_eq!(trial.bytes, text.len());
+            assert_eq!(trial.uncovered, 0);
+            assert_eq!(trial.tokens, trial.tokenizer.encode(text).len());
+            assert!(trial.tokens < text.len());
+        }
+
+        assert!(compare_patterns(text, &["(unclosed"], &TrainOptions::new(262)).is_err());
+    }
+
+    #[test]
+    fn test_compare_patterns_uncovered() {
+        let text = "the cat and the hat, then the bath";
+        let trials = compare_patterns(text, &[r"\w+"], &TrainOptions::new(262)).unwrap();
+        let trial = &trials[0];
+        // Spaces and the comma are never matched, so they are neither encoded nor counted.
+        assert_eq!(trial.uncovered, 8);
+        assert_eq!(trial.bytes, text.len() - 8);
+        assert_eq!(trial.tokens, trial.tokenizer.encode(text).len());
+        assert_eq!(trial.bytes_per_token(), trial.bytes as f64 / trial.tokens as f64);
+    }
+}
@@ -0,0 +1,274 @@
+//! Vocab tables for tools outside this crate.
+//!
+//! [`Tokenizer::export_vocab`] writes one row per token, ordered by id, with the columns
+//!
+//! * `id`: the token id,
+//! * `bytes`: the token bytes, base64 encoded,
+//! * `text`: the token rendered like the `.vocab` file, or the text of a special token,
+//! * `byte_len`: the number of bytes (0 for special tokens),
+//! * `rank`: the position of the merge that created the token (0 for the first merge, see
+//!   [`Tokenizer::rank_of`]), empty for the byte tokens and special tokens,
+//! * `special`: whether the token is a special token.
+//!
+//! Arrow output is an IPC file with a single record batch, which pandas, polars and DuckDB read
+//! directly. `id`, `byte_len` and `rank` are 32 bit unsigned integers there.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::tokenizers::basic::Tokenizer;
+use crate::util::{base64_encode, csv_field, render_token};
+use arrow_array::{ArrayRef, BooleanArray, RecordBatch, StringArray, UInt32Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use serde_json::json;
+
+/// File format of [`Tokenizer::export_vocab`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VocabFormat {
+    /// Comma separated values with a header row.
+    Csv,
+    /// A JSON array with one object per token.
+    Json,
+    /// An Arrow IPC file.
+    Arrow,
+}
+
+impl VocabFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VocabFormat::Csv => "csv",
+            VocabFormat::Json => "json",
+            VocabFormat::Arrow => "arrow",
+        }
+    }
+}
+
+impl FromStr for VocabFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(VocabFormat::Csv),
+            "json" => Ok(VocabFormat::Json),
+            "arrow" => Ok(VocabFormat::Arrow),
+            _ => Err(format!("Unknown vocab format '{}' (expected csv, json or arrow)", s)),
+        }
+    }
+}
+
+impl fmt::Display for VocabFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One row of the exported table.
+struct Row {
+    id: u32,
+    bytes: String,
+    text: String,
+    byte_len: u32,
+    rank: Option<u32>,
+    special: bool,
+}
+
+impl Tokenizer {
+    /// Writes the vocab and special tokens to `path` as a table in the given format. See the
+    /// [module documentation](crate::export) for the columns.
+    pub fn export_vocab(&self, path: &str, format: VocabFormat) -> io::Result<()> {
+        let rows = self.vocab_rows();
+        let mut file = BufWriter::new(File::create(path)?);
+        match format {
+            VocabFormat::Csv => write_csv(&mut file, &rows)?,
+            VocabFormat::Json => write_json(&mut file, &rows)?,
+            VocabFormat::Arrow => write_arrow(&mut file, &rows).map_err(io::Error::other)?,
+        }
+        file.flush()
+    }
+
+    fn vocab_rows(&self) -> Vec<Row> {
+        let mut rows: Vec<Row> = self
+            .vocab
+            .iter()
+            .map(|(&id, token)| Row {
+                id,
+                bytes: base64_encode(token),
+                text: render_token(token),
+                byte_len: token.len() as u32,
+                rank: self.rank_of(id),
+                special: false,
+            })
+            .collect();
+        rows.extend(self.special_tokens.iter().map(|(token, &id)| Row {
+            id,
+            bytes: base64_encode(token.as_bytes()),
+            text: token.clone(),
+            byte_len: 0,
+            rank: None,
+            special: true,
+        }));
+        rows.sort_unstable_by_key(|row| row.id);
+        rows
+    }
+}
+
+fn write_csv(out: &mut impl Write, rows: &[Row]) -> io::Result<()> {
+    writeln!(out, "id,bytes,text,byte_len,rank,special")?;
+    for row in rows {
+        let rank = row.rank.map_or(String::new(), |rank| rank.to_string());
+        writeln!(
+            out,
+            "{},{},{},{},{},{}",
+            row.id,
+            row.bytes,
+            csv_field(&row.text),
+            row.byte_len,
+            rank,
+            row.special
+        )?;
+    }
+    Ok(())
+}
+
+fn write_json(out: &mut impl Write, rows: &[Row]) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (i, row) in rows.iter().enumerate() {
+        let object = json!({
+            "id": row.id,
+            "bytes": row.bytes,
+            "text": row.text,
+            "byte_len": row.byte_len,
+            "rank": row.rank,
+            "special": row.special,
+        });
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(out, "  {}{}", object, separator)?;
+    }
+    writeln!(out, "]")
+}
+
+fn write_arrow(out: &mut impl Write, rows: &[Row]) -> Result<(), ArrowError> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new("bytes", DataType::Utf8, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("byte_len", DataType::UInt32, false),
+        Field::new("rank", DataType::UInt32, true),
+        Field::new("special", DataType::Boolean, false),
+    ]));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(rows.iter().map(|row| row.id))),
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|row| &row.bytes))),
+        Arc::new(StringArray::from_iter_values(rows.iter().map(|row| &row.text))),
+        Arc::new(UInt32Array::from_iter_values(rows.iter().map(|row| row.byte_len))),
+        Arc::new(UInt32Array::from_iter(rows.iter().map(|row| row.rank))),
+        Arc::new(BooleanArray::from_iter(rows.iter().map(|row| Some(row.special)))),
+    ];
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let mut writer = FileWriter::try_new(out, &schema)?;
+    writer.write(&batch)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenizerTrait;
+    use std::collections::HashMap;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn trained_tokenizer() -> Tokenizer {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("aaabdaaabac aaabdaaabac", 259, false);
+        tokenizer.register_special_tokens(HashMap::from([("<|end|>".to_string(), 259)]));
+        tokenizer
+    }
+
+    #[test]
+    fn test_export_csv() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("vocab.csv");
+        let path = path.to_str().unwrap();
+        trained_tokenizer().export_vocab(path, VocabFormat::Csv)?;
+
+        let content = fs::read_to_string(path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 1 + 260);
+        assert_eq!(lines[0], "id,bytes,text,byte_len,rank,special");
+        assert_eq!(lines[1 + 44], "44,LA==,\",\",1,,false");
+        assert_eq!(lines[1 + 97], "97,YQ==,a,1,,false");
+        assert_eq!(lines[1 + 256], "256,YWE=,aa,2,0,false");
+        assert_eq!(lines[1 + 258], "258,YWFhYg==,aaab,4,2,false");
+        assert_eq!(lines[1 + 259], "259,PHxlbmR8Pg==,<|end|>,0,,true");
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_json() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("vocab.json");
+        let path = path.to_str().unwrap();
+        trained_tokenizer().export_vocab(path, VocabFormat::Json)?;
+
+        let content = fs::read_to_string(path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2 + 260);
+        assert_eq!((lines[0], lines[261]), ("[", "]"));
+        assert_eq!(
+            lines[1 + 34],
+            r#"  {"id":34,"bytes":"Ig==","text":"\"","byte_len":1,"rank":null,"special":false},"#
+        );
+        assert_eq!(
+            lines[1 + 257],
+            r#"  {"id":257,"bytes":"YWI=","text":"ab","byte_len":2,"rank":1,"special":false},"#
+        );
+        assert!(lines[1 + 259].ends_with(r#""special":true}"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_arrow() -> io::Result<()> {
+        use arrow_array::Array;
+        use arrow_ipc::reader::FileReader;
+
+        let dir = tempdir()?;
+        let path = dir.path().join("vocab.arrow");
+        let path = path.to_str().unwrap();
+        trained_tokenizer().export_vocab(path, VocabFormat::Arrow)?;
+
+        let reader = FileReader::try_new(File::open(path)?, None).unwrap();
+        let schema = reader.schema();
+        let names: Vec<&str> = schema.fields().iter().map(|field| field.name().as_str()).collect();
+        assert_eq!(names, ["id", "bytes", "text", "byte_len", "rank", "special"]);
+        assert_eq!(schema.field(0).data_type(), &DataType::UInt32);
+        assert!(schema.field(4).is_nullable());
+
+        let batches: Vec<RecordBatch> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 260);
+        let column = |i: usize| batch.column(i).as_any();
+        let ids = column(0).downcast_ref::<UInt32Array>().unwrap();
+        let texts = column(2).downcast_ref::<StringArray>().unwrap();
+        let ranks = column(4).downcast_ref::<UInt32Array>().unwrap();
+        let special = column(5).downcast_ref::<BooleanArray>().unwrap();
+        assert_eq!((ids.value(258), texts.value(258), ranks.value(258)), (258, "aaab", 2));
+        assert!(ranks.is_null(97));
+        assert_eq!(ranks.null_count(), 257);
+        assert_eq!(texts.value(259), "<|end|>");
+        assert!(special.value(259) && !special.value(258));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_from_str_roundtrip() {
+        for format in [VocabFormat::Csv, VocabFormat::Json, VocabFormat::Arrow] {
+            assert_eq!(format.as_str().parse::<VocabFormat>(), Ok(format));
+        }
+        assert!("parquet".parse::<VocabFormat>().is_err());
+    }
+}
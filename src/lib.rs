@@ -1,12 +1,178 @@
+//! Byte pair encoding tokenizers, ported from minbpe.
+//!
+//! With the default `std` feature the crate reads and writes model files, trains on files and
+//! threads, and provides the analysis modules used by the `rbpe` CLI. Without it the crate is
+//! `no_std` and only needs `alloc`: [`TokenizerTrait`] can still train, encode and decode, and
+//! models are loaded from byte slices with [`TokenizerTrait::load_bytes`] or [`load_model_bytes`].
+//! The literal prefilters of `regex` are only enabled with `std`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod align;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod cost;
+#[cfg(feature = "std")]
+pub mod eval;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod normalizer;
+pub mod pair_counter;
+#[cfg(feature = "std")]
+pub mod repl;
 pub mod tokenizers;
+pub mod train;
 pub mod util;
 
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io;
 
-pub trait TokenizerTrait {
-    fn train(&mut self, text: &str, vocab_size: u32, verbose: bool);
-    fn encode(&self, text: &str) -> Vec<u32>;
+use tokenizers::basic::Tokenizer;
+use tokenizers::regex::RegexTokenizer;
+use train::TrainOptions;
+
+/// The map used by the tokenizers: the standard `HashMap` with the `std` feature, and
+/// `hashbrown`'s map with a fixed hasher without it.
+#[cfg(feature = "std")]
+pub use std::collections::HashMap;
+#[cfg(not(feature = "std"))]
+pub type HashMap<K, V> = hashbrown::HashMap<K, V, core::hash::BuildHasherDefault<util::FxHasher>>;
+
+/// Common interface of the tokenizers. `as_ref` gives access to the merges and vocab.
+pub trait TokenizerTrait: AsRef<Tokenizer> {
+    fn train(&mut self, text: &str, vocab_size: u32, verbose: bool) {
+        self.train_with_options(text, &TrainOptions { verbose, ..TrainOptions::new(vocab_size) });
+    }
+    fn train_with_options(&mut self, text: &str, options: &TrainOptions);
+    fn encode(&self, text: &str) -> Vec<u32> {
+        let mut ids = Vec::new();
+        self.encode_into(text, &mut EncodeScratch::new(), &mut ids);
+        ids
+    }
+    /// Encodes `text` like `encode` and appends the ids to `out`, reusing the buffers in
+    /// `scratch`. Encoding many short texts with the same scratch and output vector (cleared
+    /// between calls) does not allocate once the buffers have grown.
+    fn encode_into(&self, text: &str, scratch: &mut EncodeScratch, out: &mut Vec<u32>);
+    /// Decodes ids to text. Ids that are neither in the vocab nor special tokens are skipped;
+    /// use `decode_strict` or `decode_with_placeholder` to find them.
     fn decode(&self, ids: &[u32]) -> String;
+    /// Decodes like `decode`, but fails on the first unknown id instead of skipping it. Invalid
+    /// UTF-8 is replaced with U+FFFD.
+    fn decode_strict(&self, ids: &[u32]) -> Result<String, UnknownId> {
+        let tokenizer = self.as_ref();
+        let mut bytes = Vec::new();
+        for (position, &id) in ids.iter().enumerate() {
+            match tokenizer.token_bytes(id) {
+                Some(token) => bytes.extend_from_slice(token),
+                None => return Err(UnknownId { id, position }),
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+    /// Decodes like `decode_strict`, but writes `placeholder` in place of every unknown id and
+    /// returns all of them alongside the text.
+    fn decode_with_placeholder(&self, ids: &[u32], placeholder: &str) -> (String, Vec<UnknownId>) {
+        let tokenizer = self.as_ref();
+        let mut bytes = Vec::new();
+        let mut unknown = Vec::new();
+        for (position, &id) in ids.iter().enumerate() {
+            match tokenizer.token_bytes(id) {
+                Some(token) => bytes.extend_from_slice(token),
+                None => {
+                    bytes.extend_from_slice(placeholder.as_bytes());
+                    unknown.push(UnknownId { id, position });
+                }
+            }
+        }
+        (String::from_utf8_lossy(&bytes).into_owned(), unknown)
+    }
+    /// Replaces the merges, vocab and special tokens with a model in the `.model` format.
+    fn load_bytes(&mut self, model: &[u8]) -> Result<(), ModelError>;
+    #[cfg(feature = "std")]
     fn save(&self, file_prefix: &str) -> io::Result<()>;
-    fn load(&mut self, model_file: &str) -> io::Result<()>;
+    #[cfg(feature = "std")]
+    fn load(&mut self, model_file: &str) -> io::Result<()> {
+        assert!(model_file.ends_with(".model"));
+        self.load_bytes(&std::fs::read(model_file)?)?;
+        Ok(())
+    }
+}
+
+/// Working memory for [`TokenizerTrait::encode_into`], kept between calls to avoid allocating.
+#[derive(Debug, Default)]
+pub struct EncodeScratch {
+    pub(crate) ids: Vec<u32>,
+}
+
+impl EncodeScratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// An id that is neither in the vocab nor a special token, at `position` in the decoded ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownId {
+    pub id: u32,
+    pub position: usize,
+}
+
+impl fmt::Display for UnknownId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown token id {} at position {}", self.id, self.position)
+    }
+}
+
+impl core::error::Error for UnknownId {}
+
+/// A model that could not be loaded because its content is malformed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelError(String);
+
+impl ModelError {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        ModelError(message.into())
+    }
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl core::error::Error for ModelError {}
+
+#[cfg(feature = "std")]
+impl From<ModelError> for io::Error {
+    fn from(error: ModelError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error.0)
+    }
+}
+
+/// Loads a model saved by either tokenizer: models with a split pattern are loaded as a
+/// [`RegexTokenizer`], models without one as a basic [`Tokenizer`].
+#[cfg(feature = "std")]
+pub fn load_model(model_file: &str) -> io::Result<Box<dyn TokenizerTrait>> {
+    Ok(load_model_bytes(&std::fs::read(model_file)?)?)
+}
+
+/// Loads the content of a `.model` file like [`load_model`].
+pub fn load_model_bytes(model: &[u8]) -> Result<Box<dyn TokenizerTrait>, ModelError> {
+    let mut tokenizer = Tokenizer::new();
+    tokenizer.load_bytes(model)?;
+    if tokenizer.pattern.is_empty() {
+        return Ok(Box::new(tokenizer));
+    }
+    let tokenizer = RegexTokenizer::from_tokenizer(tokenizer)
+        .map_err(|e| ModelError::new(alloc::format!("Invalid split pattern: {}", e)))?;
+    Ok(Box::new(tokenizer))
 }
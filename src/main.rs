@@ -1,11 +1,11 @@
 //! This is the main file of the RBPE (Rule-Based Preprocessor Engine) CLI application.
 //!
-//! The application reads the content of a file, trains a tokenizer based on the chosen algorithm,
-//! and saves the trained tokenizer model to a file.
+//! The `train` subcommand reads the content of the training inputs, trains a tokenizer based on
+//! the chosen algorithm, and saves the trained tokenizer model to a file.
 //!
-//! The CLI accepts a command-line argument `--tokenizer` to specify the tokenizer algorithm.
-//! The available choices are "basic" and "regex". If no choice is provided, the default tokenizer
-//! algorithm is "regex".
+//! The subcommand accepts a command-line argument `--tokenizer` to specify the tokenizer
+//! algorithm. The available choices are "basic" and "regex". If no choice is provided, the
+//! default tokenizer algorithm is "regex".
 //!
 //! The application measures the performance of the training process using the `Instant` struct
 //! from the `std::time` module.
@@ -13,10 +13,109 @@
 //! # Example
 //!
 //! ```shell
-//! $ cargo run -- --tokenizer basic
+//! $ cargo run -- train --tokenizer basic
 //! ```
 //!
-//! This will train the tokenizer using the basic algorithm and save the model to the "models/basic" file.
+//! This will train the tokenizer using the basic algorithm on "data/taylorswift.txt" and save
+//! the model to "models/basic-512.model" (plus the matching ".vocab" file).
+//!
+//! Use `--input` to train on other files, `--vocab-size` to change the vocabulary size and
+//! `--output` to choose the file prefix of the trained model. Parent directories of the prefix
+//! are created as needed, and existing models are only overwritten when `--force` is passed:
+//!
+//! ```shell
+//! $ cargo run -- train --tokenizer regex --vocab-size 32768 --output models/my-vocab-32k --force
+//! ```
+//!
+//! A training run can also be described by a TOML file (see `rbpe::config`), which makes it
+//! reviewable and reproducible:
+//!
+//! ```shell
+//! $ cargo run -- train --config train.toml
+//! ```
+//!
+//! Either way the resolved configuration is written next to the model as `<prefix>.toml`, so
+//! `train --config <prefix>.toml` reproduces the run.
+//!
+//! Instead of guessing the vocab size, pass a large one with `--min-gain` to stop once a merge
+//! shrinks the corpus by less than that fraction of its tokens, and `--curve` to write the
+//! frequency and gain of every merge to `<prefix>.curve.csv`:
+//!
+//! ```shell
+//! $ cargo run -- train --vocab-size 65536 --min-gain 0.0001 --curve
+//! ```
+//!
+//! The `align` subcommand maps the ids of a new vocab onto an old one, to initialize embedding
+//! rows when a model switches vocabularies:
+//!
+//! ```shell
+//! $ cargo run -- align --old models/old.model --new models/new.model --output alignment.csv
+//! ```
+//!
+//! The `repl` subcommand opens an interactive prompt to explore a trained vocab (type `:help`
+//! for the commands):
+//!
+//! ```shell
+//! $ cargo run -- repl --model models/regex-512.model
+//! ```
+//!
+//! The `find` subcommand searches a vocab for tokens containing some text or whose bytes match a
+//! regex, e.g. to audit a vocab for leaked names:
+//!
+//! ```shell
+//! $ cargo run -- find --model models/regex-512.model --contains swift
+//! $ cargo run -- find --model models/regex-512.model --regex '^[0-9]{3}$'
+//! ```
+//!
+//! The `coverage` subcommand encodes a corpus and reports bytes per token for every Unicode
+//! script in it, to show which languages a vocab serves poorly:
+//!
+//! ```shell
+//! $ cargo run -- coverage --model models/regex-512.model --input data/taylorswift.txt
+//! ```
+//!
+//! The `verify` subcommand encodes and decodes every input and fails if any of them does not
+//! come back unchanged, printing the byte offset and ids of the first difference. Pass `--lines`
+//! to check every line as a separate document:
+//!
+//! ```shell
+//! $ cargo run -- verify --model models/regex-512.model --input data/taylorswift.txt --lines
+//! ```
+//!
+//! The `cost` subcommand counts the tokens of prompt files (JSON Lines, see `rbpe::cost`, or
+//! plain text counted as a single prompt) and projects the API cost from prices per million
+//! tokens. The model can be a trained model, a tiktoken rank file, or the name of a rank file in
+//! `models/` (`cl100k` finds `models/cl100k_base.tiktoken`):
+//!
+//! ```shell
+//! $ cargo run -- cost --model cl100k --input prompts.jsonl --price-in 2.50 --price-out 10.0
+//! ```
+//!
+//! The `export` subcommand writes the vocab as a table of ids, base64 bytes, rendered text, byte
+//! lengths and merge ranks (see `rbpe::export`) in CSV, JSON or Arrow format, for spreadsheets,
+//! pandas or DuckDB:
+//!
+//! ```shell
+//! $ cargo run -- export --model models/regex-512.model --output vocab.arrow --format arrow
+//! ```
+//!
+//! The `decode` subcommand turns ids (separated by spaces or commas, from `--ids` or standard
+//! input) back into text. It fails on the first id that the model does not know, unless
+//! `--placeholder` is given, in which case unknown ids are replaced and summarized on stderr:
+//!
+//! ```shell
+//! $ echo "72 101 108 108 111" | cargo run -- decode --model models/regex-512.model
+//! $ cargo run -- decode --model models/regex-512.model --ids "72, 9999" --placeholder "<?>"
+//! ```
+//!
+//! The `pattern-lab` subcommand compares candidate split patterns: it trains a small vocab on
+//! the corpus with each of them and prints training time, pre-token and token counts, bytes per
+//! token and the segmentation of a few example lines side by side (`gpt4` stands for the GPT-4
+//! pattern):
+//!
+//! ```shell
+//! $ cargo run -- pattern-lab --input data/taylorswift.txt --pattern gpt4 --pattern '\w+|\W+'
+//! ```
 //!
 //! # Dependencies
 //!
@@ -30,56 +129,169 @@
 //! To run the application, use the following command:
 //!
 //! ```shell
-//! $ cargo run -- [OPTIONS]
+//! $ cargo run -- <SUBCOMMAND> [OPTIONS]
 //! ```
 //!
 //! Replace `[OPTIONS]` with the desired command-line options.
 //!
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 use std::{fs, io};
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use rbpe::align::{alignment, write_alignment_csv};
+use rbpe::config::TrainConfig;
+use rbpe::cost::{count_jsonl, count_text, Pricing, Usage};
+use rbpe::eval::{compare_patterns, script_coverage, VerifyRoundtrip};
+use rbpe::export::VocabFormat;
+use rbpe::normalizer::Normalizer;
+use rbpe::repl::Repl;
 use rbpe::tokenizers::basic::Tokenizer;
-use rbpe::tokenizers::regex::RegexTokenizer;
-use rbpe::TokenizerTrait;
+use rbpe::tokenizers::regex::{RegexTokenizer, GPT4_SPLIT_PATTERN};
+use rbpe::train::{write_growth_curve_csv, TrainOptions};
+use rbpe::util::render_token;
+use rbpe::{load_model, TokenizerTrait, UnknownId};
+use regex::bytes::Regex;
 
-fn read_file_content(path: &Path) -> io::Result<String> {
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    Ok(contents)
+fn invalid_input<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
 }
 
-fn main() -> std::io::Result<()> {
-    let matches = App::new("CLI for tokenizer")
-        .arg(
-            Arg::with_name("tokenizer")
-                .long("tokenizer")
-                .value_name("TOKENIZER")
-                .help("Choose a tokenizer for processing (e.g. basic, regex)")
-                .takes_value(true),
-        )
-        .get_matches();
+/// Returns the file prefix for the trained model: either the user supplied `--output` or
+/// `models/<tokenizer>-<vocab_size>`.
+fn output_prefix(output: Option<&Path>, tokenizer: &str, vocab_size: u32) -> PathBuf {
+    match output {
+        Some(output) => output.to_path_buf(),
+        None => Path::new("models").join(format!("{}-{}", tokenizer, vocab_size)),
+    }
+}
+
+/// Creates the parent directories of `file_prefix` and refuses to clobber an existing model
+/// unless `force` is set.
+fn prepare_output(file_prefix: &Path, force: bool) -> io::Result<()> {
+    if let Some(parent) = file_prefix.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    if force {
+        return Ok(());
+    }
+    for extension in ["model", "vocab", "toml"] {
+        let path = PathBuf::from(format!("{}.{}", file_prefix.display(), extension));
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists, pass --force to overwrite it", path.display()),
+            ));
+        }
+    }
+    Ok(())
+}
 
-    let choices = matches.value_of("tokenizer").unwrap_or("regex");
+/// Builds the training config from `--config` or from the individual command-line flags.
+fn train_config(matches: &ArgMatches) -> io::Result<TrainConfig> {
+    let mut config = match matches.value_of("config") {
+        Some(path) => TrainConfig::from_file(Path::new(path))?,
+        None => {
+            let tokenizer = match matches.value_of("tokenizer").unwrap_or("regex") {
+                "basic" => "basic",
+                _ => "regex",
+            };
+            let vocab_size = match matches.value_of("vocab-size") {
+                Some(value) => value
+                    .parse::<u32>()
+                    .map_err(|e| invalid_input(format!("Invalid vocab size: {}", e)))?,
+                None => 512,
+            };
+            if vocab_size < 256 {
+                return Err(invalid_input("Vocab size must be at least 256"));
+            }
+            let inputs: Vec<PathBuf> = match matches.values_of("input") {
+                Some(values) => values.map(PathBuf::from).collect(),
+                None => vec![PathBuf::from("data/taylorswift.txt")],
+            };
+            TrainConfig {
+                tokenizer: tokenizer.to_string(),
+                weights: vec![1; inputs.len()],
+                inputs,
+                vocab_size,
+                pattern: match tokenizer {
+                    "basic" => String::new(),
+                    _ => GPT4_SPLIT_PATTERN.to_string(),
+                },
+                normalizer: Normalizer::None,
+                special_tokens: Vec::new(),
+                output: None,
+                max_token_len: None,
+                whitespace_guard: false,
+                blocklist: Vec::new(),
+                blocklist_patterns: Vec::new(),
+                min_gain: None,
+            }
+        }
+    };
+    if let Some(output) = matches.value_of("output") {
+        config.output = Some(PathBuf::from(output));
+    }
+    if let Some(value) = matches.value_of("max-token-len") {
+        let max_token_len = value
+            .parse::<usize>()
+            .map_err(|e| invalid_input(format!("Invalid max token length: {}", e)))?;
+        config.max_token_len = Some(max_token_len);
+    }
+    if matches.is_present("whitespace-guard") {
+        config.whitespace_guard = true;
+    }
+    if let Some(values) = matches.values_of("block") {
+        config.blocklist.extend(values.map(String::from));
+    }
+    if let Some(values) = matches.values_of("block-pattern") {
+        config.blocklist_patterns.extend(values.map(String::from));
+    }
+    if let Some(value) = matches.value_of("min-gain") {
+        match value.parse::<f64>() {
+            Ok(min_gain) if (0.0..=1.0).contains(&min_gain) => config.min_gain = Some(min_gain),
+            _ => return Err(invalid_input(format!("Invalid min gain '{}'", value))),
+        }
+    }
+    Ok(config)
+}
+
+fn train(matches: &ArgMatches) -> io::Result<()> {
+    let mut config = train_config(matches)?;
+    let file_prefix = output_prefix(config.output.as_deref(), &config.tokenizer, config.vocab_size);
+    config.output = Some(file_prefix.clone());
+    prepare_output(&file_prefix, matches.is_present("force"))?;
+    let content = config.load_corpus()?;
 
-    let mut tokenizer: Box<dyn TokenizerTrait> = match choices {
-        "basic" => Box::new(Tokenizer::new()),
-        "regex" => Box::new(RegexTokenizer::new()),
-        _ => Box::new(RegexTokenizer::new()),
+    let special_tokens: HashMap<String, u32> = config
+        .special_tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| (token.clone(), config.vocab_size + i as u32))
+        .collect();
+    let mut tokenizer: Box<dyn TokenizerTrait> = match config.tokenizer.as_str() {
+        "basic" => {
+            let mut tokenizer = Tokenizer::new();
+            tokenizer.register_special_tokens(special_tokens);
+            Box::new(tokenizer)
+        }
+        _ => {
+            let mut tokenizer = RegexTokenizer::with_pattern(&config.pattern)
+                .map_err(|e| invalid_input(format!("Invalid pattern: {}", e)))?;
+            tokenizer.register_special_tokens(special_tokens);
+            Box::new(tokenizer)
+        }
     };
-    let training_input_path = "data/taylorswift.txt";
-    let content = read_file_content(Path::new(training_input_path))?;
-    fs::create_dir_all("models")?;
 
     // Time the performance
     let start = Instant::now();
-    let file_prefix = Path::new("models").join(choices);
 
-    tokenizer.train(&content, 512, true);
+    let options =
+        TrainOptions { record_curve: matches.is_present("curve"), ..config.train_options(true)? };
+    tokenizer.train_with_options(&content, &options);
     if let Some(file_prefix_str) = file_prefix.to_str() {
         tokenizer.save(file_prefix_str)?;
     } else {
@@ -88,7 +300,686 @@ fn main() -> std::io::Result<()> {
             "Path contain invalid utf-8 characters",
         ));
     }
+    fs::write(format!("{}.toml", file_prefix.display()), config.to_toml_string())?;
+    if options.record_curve {
+        let curve_path = format!("{}.curve.csv", file_prefix.display());
+        write_growth_curve_csv(&curve_path, &(*tokenizer).as_ref().growth_curve)?;
+    }
     let duration = start.elapsed();
     println!("Took {:.2}", duration.as_secs_f32());
     Ok(())
 }
+
+fn load_tokenizer(model_file: &str) -> io::Result<Tokenizer> {
+    let mut tokenizer = Tokenizer::new();
+    tokenizer.load(model_file)?;
+    Ok(tokenizer)
+}
+
+fn align(matches: &ArgMatches) -> io::Result<()> {
+    let old = load_tokenizer(matches.value_of("old").unwrap())?;
+    let new = load_tokenizer(matches.value_of("new").unwrap())?;
+    let output = matches.value_of("output").unwrap();
+    write_alignment_csv(output, &old, &new)?;
+
+    let aligned = alignment(&old, &new);
+    let exact = aligned.iter().filter(|old_id| old_id.is_some()).count();
+    println!(
+        "{} of {} new tokens exist in the old vocab, wrote {}",
+        exact,
+        new.vocab.len(),
+        output
+    );
+    Ok(())
+}
+
+fn repl(matches: &ArgMatches) -> io::Result<()> {
+    let model = load_model(matches.value_of("model").unwrap())?;
+    let vocab_size = (*model).as_ref().vocab.len();
+    println!("Loaded {} tokens, type :help for the commands", vocab_size);
+    Repl::new(&*model).run(io::stdin().lock(), io::stdout())
+}
+
+fn find(matches: &ArgMatches) -> io::Result<()> {
+    let tokenizer = load_tokenizer(matches.value_of("model").unwrap())?;
+    let found = match (matches.value_of("contains"), matches.value_of("regex")) {
+        (Some(needle), _) => tokenizer.find_tokens_containing(needle.as_bytes()),
+        (None, Some(pattern)) => {
+            let pattern =
+                Regex::new(pattern).map_err(|e| invalid_input(format!("Invalid regex: {}", e)))?;
+            tokenizer.find_tokens_matching(&pattern)
+        }
+        (None, None) => return Err(invalid_input("Pass --contains or --regex")),
+    };
+    for (id, token) in &found {
+        println!("{} [{}]", id, render_token(token));
+    }
+    eprintln!("{} tokens match", found.len());
+    Ok(())
+}
+
+fn coverage(matches: &ArgMatches) -> io::Result<()> {
+    let model = load_model(matches.value_of("model").unwrap())?;
+    let mut text = String::new();
+    for input in matches.values_of("input").unwrap() {
+        text.push_str(&fs::read_to_string(input)?);
+    }
+    println!(
+        "{:<12} {:>12} {:>10} {:>10} {:>12}",
+        "script", "bytes", "chars", "tokens", "bytes/token"
+    );
+    for (script, coverage) in script_coverage(&*model, &text) {
+        println!(
+            "{:<12} {:>12} {:>10} {:>10} {:>12.2}",
+            script,
+            coverage.bytes,
+            coverage.chars,
+            coverage.tokens,
+            coverage.bytes_per_token()
+        );
+    }
+    Ok(())
+}
+
+fn verify(matches: &ArgMatches) -> io::Result<()> {
+    let model = load_model(matches.value_of("model").unwrap())?;
+    let mut documents = 0;
+    let mut failed = 0;
+    for input in matches.values_of("input").unwrap() {
+        let content = fs::read_to_string(input)?;
+        let corpus: Vec<&str> = if matches.is_present("lines") {
+            content.lines().collect()
+        } else {
+            vec![content.as_str()]
+        };
+        documents += corpus.len();
+        for failure in model.verify_roundtrip(&corpus) {
+            failed += 1;
+            let location = if matches.is_present("lines") {
+                format!("{}:{}", input, failure.document + 1)
+            } else {
+                input.to_string()
+            };
+            println!("{}: differs at byte {} (ids {:?})", location, failure.offset, failure.ids);
+        }
+    }
+    if failed > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} of {} documents failed to roundtrip", failed, documents),
+        ));
+    }
+    println!("All {} documents roundtrip", documents);
+    Ok(())
+}
+
+/// Loads a model for counting tokens: a `.tiktoken` rank file, a saved model, or the name of a
+/// rank file in `models/`.
+fn load_counting_model(model: &str) -> io::Result<Box<dyn TokenizerTrait>> {
+    if model.ends_with(".tiktoken") {
+        return Ok(Box::new(RegexTokenizer::from_tiktoken(model)?));
+    }
+    if model.ends_with(".model") {
+        return load_model(model);
+    }
+    for candidate in
+        [format!("models/{}.tiktoken", model), format!("models/{}_base.tiktoken", model)]
+    {
+        if Path::new(&candidate).exists() {
+            return Ok(Box::new(RegexTokenizer::from_tiktoken(&candidate)?));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "No model '{}': pass a .model or .tiktoken file, or put {}.tiktoken or \
+             {}_base.tiktoken in models/",
+            model, model, model
+        ),
+    ))
+}
+
+fn parse_price(matches: &ArgMatches, name: &str) -> io::Result<f64> {
+    let price = matches.value_of(name).unwrap();
+    match price.parse::<f64>() {
+        Ok(price) if price >= 0.0 => Ok(price),
+        _ => Err(invalid_input(format!("Invalid --{} '{}'", name, price))),
+    }
+}
+
+fn cost(matches: &ArgMatches) -> io::Result<()> {
+    let model = load_counting_model(matches.value_of("model").unwrap())?;
+    let pricing = Pricing {
+        input_per_million: parse_price(matches, "price-in")?,
+        output_per_million: parse_price(matches, "price-out")?,
+    };
+    let output_tokens: usize = matches
+        .value_of("output-tokens")
+        .unwrap()
+        .parse()
+        .map_err(|_| invalid_input("Invalid --output-tokens"))?;
+
+    let print_row = |name: &str, usage: &Usage| {
+        println!(
+            "{:<30} {:>9} {:>14} {:>14} {:>12.4}",
+            name,
+            usage.requests,
+            usage.input_tokens,
+            usage.output_tokens,
+            usage.cost(&pricing)
+        );
+    };
+    println!(
+        "{:<30} {:>9} {:>14} {:>14} {:>12}",
+        "file", "requests", "input tokens", "output tokens", "cost"
+    );
+    let mut total = Usage::default();
+    for input in matches.values_of("input").unwrap() {
+        let content = fs::read_to_string(input)?;
+        let usage = if input.ends_with(".jsonl") {
+            count_jsonl(&*model, &content, output_tokens)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", input, e)))?
+        } else {
+            count_text(&*model, &content, output_tokens)
+        };
+        print_row(input, &usage);
+        total += usage;
+    }
+    print_row("total", &total);
+    Ok(())
+}
+
+fn export(matches: &ArgMatches) -> io::Result<()> {
+    let model = load_counting_model(matches.value_of("model").unwrap())?;
+    let format: VocabFormat = matches.value_of("format").unwrap().parse().map_err(invalid_input)?;
+    let output = matches.value_of("output").unwrap();
+    let tokenizer = (*model).as_ref();
+    tokenizer.export_vocab(output, format)?;
+    println!(
+        "Wrote {} tokens to {}",
+        tokenizer.vocab.len() + tokenizer.special_tokens.len(),
+        output
+    );
+    Ok(())
+}
+
+fn parse_ids(text: &str) -> io::Result<Vec<u32>> {
+    text.split(|ch: char| ch == ',' || ch.is_whitespace())
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse().map_err(|_| invalid_input(format!("'{}' is not a token id", id))))
+        .collect()
+}
+
+/// Describes unknown ids by how often each occurred and where it first did.
+fn unknown_id_summary(unknown: &[UnknownId]) -> String {
+    let mut ids: Vec<(u32, usize, usize)> = Vec::new();
+    for &UnknownId { id, position } in unknown {
+        match ids.iter_mut().find(|(known, _, _)| *known == id) {
+            Some((_, count, _)) => *count += 1,
+            None => ids.push((id, 1, position)),
+        }
+    }
+    let listed: Vec<String> = ids
+        .iter()
+        .take(10)
+        .map(|(id, count, first)| format!("{} ({}x, first at {})", id, count, first))
+        .collect();
+    let more = if ids.len() > 10 { format!(" and {} more", ids.len() - 10) } else { String::new() };
+    format!("{} unknown ids: {}{}", unknown.len(), listed.join(", "), more)
+}
+
+fn decode(matches: &ArgMatches) -> io::Result<()> {
+    let model = load_counting_model(matches.value_of("model").unwrap())?;
+    let ids = match matches.value_of("ids") {
+        Some(ids) => parse_ids(ids)?,
+        None => parse_ids(&io::read_to_string(io::stdin())?)?,
+    };
+    let text = match matches.value_of("placeholder") {
+        Some(placeholder) => {
+            let (text, unknown) = model.decode_with_placeholder(&ids, placeholder);
+            if !unknown.is_empty() {
+                eprintln!("warning: replaced {}", unknown_id_summary(&unknown));
+            }
+            text
+        }
+        None => model
+            .decode_strict(&ids)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?,
+    };
+    println!("{}", text);
+    Ok(())
+}
+
+/// Returns the first `max_chars` characters of `text`.
+fn truncate_chars(text: &str, max_chars: usize) -> &str {
+    text.char_indices().nth(max_chars).map_or(text, |(end, _)| &text[..end])
+}
+
+fn pattern_lab(matches: &ArgMatches) -> io::Result<()> {
+    let mut text = String::new();
+    for input in matches.values_of("input").unwrap() {
+        text.push_str(&fs::read_to_string(input)?);
+    }
+    let patterns: Vec<&str> = matches
+        .values_of("pattern")
+        .unwrap()
+        .map(|pattern| if pattern == "gpt4" { GPT4_SPLIT_PATTERN } else { pattern })
+        .collect();
+    let vocab_size = matches
+        .value_of("vocab-size")
+        .unwrap()
+        .parse::<u32>()
+        .map_err(|e| invalid_input(format!("Invalid vocab size: {}", e)))?;
+    if vocab_size < 256 {
+        return Err(invalid_input("Vocab size must be at least 256"));
+    }
+    let examples: Vec<&str> = match matches.values_of("example") {
+        Some(examples) => examples.collect(),
+        None => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .take(3)
+            .map(|line| truncate_chars(line, 80))
+            .collect(),
+    };
+
+    let trials = compare_patterns(&text, &patterns, &TrainOptions::new(vocab_size))
+        .map_err(|e| invalid_input(format!("Invalid pattern: {}", e)))?;
+    println!("Trained a {} token vocab on {} bytes with every pattern\n", vocab_size, text.len());
+    println!(
+        "{:<3} {:>10} {:>10} {:>10} {:>12} {:>10}  pattern",
+        "#", "train (s)", "chunks", "tokens", "bytes/token", "uncovered"
+    );
+    for (i, (trial, pattern)) in trials.iter().zip(&patterns).enumerate() {
+        println!(
+            "{:<3} {:>10.2} {:>10} {:>10} {:>12.2} {:>10}  {}",
+            i + 1,
+            trial.train_time.as_secs_f64(),
+            trial.chunks,
+            trial.tokens,
+            trial.bytes_per_token(),
+            trial.uncovered,
+            pattern
+        );
+    }
+    if trials.iter().any(|trial| trial.uncovered > 0) {
+        eprintln!(
+            "\nwarning: some patterns do not match the whole text, the uncovered bytes are dropped"
+        );
+    }
+    for example in examples {
+        println!("\n{:?}", example);
+        for (i, trial) in trials.iter().enumerate() {
+            let vocab = &trial.tokenizer.tokenizer().vocab;
+            let pieces: Vec<String> = trial
+                .tokenizer
+                .encode(example)
+                .iter()
+                .map(|id| format!("[{}]", render_token(&vocab[id])))
+                .collect();
+            println!("{:<3} {}", i + 1, pieces.join(""));
+        }
+    }
+    Ok(())
+}
+
+fn main() -> std::io::Result<()> {
+    let matches = App::new("CLI for tokenizer")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(
+            App::new("train")
+                .about("Train a tokenizer and save the model")
+                .arg(
+                    Arg::with_name("config")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("TOML file describing the training run")
+                        .takes_value(true)
+                        .conflicts_with_all(&["tokenizer", "vocab-size", "input"]),
+                )
+                .arg(
+                    Arg::with_name("tokenizer")
+                        .long("tokenizer")
+                        .value_name("TOKENIZER")
+                        .help("Choose a tokenizer for processing (e.g. basic, regex)")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("vocab-size")
+                        .long("vocab-size")
+                        .value_name("VOCAB_SIZE")
+                        .help("Size of the vocabulary to train, including the 256 byte tokens")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Training input, may be repeated (default: data/taylorswift.txt)")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("PREFIX")
+                        .help(
+                            "File prefix of the trained model \
+                             (default: models/<tokenizer>-<vocab size>)",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-token-len")
+                        .long("max-token-len")
+                        .value_name("BYTES")
+                        .help("Never learn tokens longer than this many bytes")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("whitespace-guard")
+                        .long("whitespace-guard")
+                        .help("Never learn tokens that continue past whitespace into a new word"),
+                )
+                .arg(
+                    Arg::with_name("min-gain")
+                        .long("min-gain")
+                        .value_name("FRACTION")
+                        .help("Stop once a merge shrinks the corpus by less than this fraction")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("curve")
+                        .long("curve")
+                        .help("Write the gain of every merge to <prefix>.curve.csv"),
+                )
+                .arg(
+                    Arg::with_name("block")
+                        .long("block")
+                        .value_name("TEXT")
+                        .help("Never learn tokens containing TEXT, may be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::with_name("block-pattern")
+                        .long("block-pattern")
+                        .value_name("REGEX")
+                        .help("Never learn tokens matching REGEX, may be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Overwrite an existing model with the same prefix"),
+                ),
+        )
+        .subcommand(
+            App::new("align")
+                .about("Map the ids of a new vocab to the ids of an old vocab")
+                .arg(
+                    Arg::with_name("old")
+                        .long("old")
+                        .value_name("MODEL")
+                        .help("Model file of the old vocab")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .long("new")
+                        .value_name("MODEL")
+                        .help("Model file of the new vocab")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("CSV file to write the alignment to")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("repl").about("Explore a trained vocab interactively").arg(
+                Arg::with_name("model")
+                    .long("model")
+                    .value_name("MODEL")
+                    .help("Model file to load")
+                    .takes_value(true)
+                    .required(true),
+            ),
+        )
+        .subcommand(
+            App::new("find")
+                .about("Search a vocab for tokens")
+                .arg(
+                    Arg::with_name("model")
+                        .long("model")
+                        .value_name("MODEL")
+                        .help("Model file to search")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("contains")
+                        .long("contains")
+                        .value_name("TEXT")
+                        .help("Find tokens whose bytes contain TEXT")
+                        .takes_value(true)
+                        .conflicts_with("regex"),
+                )
+                .arg(
+                    Arg::with_name("regex")
+                        .long("regex")
+                        .value_name("PATTERN")
+                        .help("Find tokens whose bytes match PATTERN")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("coverage")
+                .about("Report bytes per token for every script in a corpus")
+                .arg(
+                    Arg::with_name("model")
+                        .long("model")
+                        .value_name("MODEL")
+                        .help("Model file to evaluate")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Text file to encode, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("verify")
+                .about("Check that a corpus survives encoding and decoding")
+                .arg(
+                    Arg::with_name("model")
+                        .long("model")
+                        .value_name("MODEL")
+                        .help("Model file to verify")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Text file to roundtrip, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("lines")
+                        .long("lines")
+                        .help("Check every line as a separate document"),
+                ),
+        )
+        .subcommand(
+            App::new("cost")
+                .about("Count prompt tokens and project API cost")
+                .arg(
+                    Arg::with_name("model")
+                        .long("model")
+                        .value_name("MODEL")
+                        .help("Model file, tiktoken file, or name of a tiktoken file in models/")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("JSON Lines prompt file or text file, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("price-in")
+                        .long("price-in")
+                        .value_name("PRICE")
+                        .help("Price per million input tokens")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("price-out")
+                        .long("price-out")
+                        .value_name("PRICE")
+                        .help("Price per million output tokens")
+                        .takes_value(true)
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::with_name("output-tokens")
+                        .long("output-tokens")
+                        .value_name("TOKENS")
+                        .help("Output tokens expected for requests without a completion")
+                        .takes_value(true)
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(
+            App::new("export")
+                .about("Write the vocab as a table for other tools")
+                .arg(
+                    Arg::with_name("model")
+                        .long("model")
+                        .value_name("MODEL")
+                        .help("Model file, tiktoken file, or name of a tiktoken file in models/")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("File to write the table to")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Table format: csv, json or arrow")
+                        .takes_value(true)
+                        .default_value("csv"),
+                ),
+        )
+        .subcommand(
+            App::new("decode")
+                .about("Decode token ids to text")
+                .arg(
+                    Arg::with_name("model")
+                        .long("model")
+                        .value_name("MODEL")
+                        .help("Model file, tiktoken file, or name of a tiktoken file in models/")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("ids")
+                        .long("ids")
+                        .value_name("IDS")
+                        .help("Ids separated by spaces or commas, read from stdin if omitted")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("placeholder")
+                        .long("placeholder")
+                        .value_name("TEXT")
+                        .help("Write TEXT for unknown ids instead of failing")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("pattern-lab")
+                .about("Compare split patterns by training a small vocab with each")
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Text file to train on, can be repeated")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("pattern")
+                        .long("pattern")
+                        .value_name("REGEX")
+                        .help(
+                            "Split pattern to try, or gpt4 for the GPT-4 pattern, can be repeated",
+                        )
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("vocab-size")
+                        .long("vocab-size")
+                        .value_name("SIZE")
+                        .help("Vocab size to train with every pattern")
+                        .takes_value(true)
+                        .default_value("512"),
+                )
+                .arg(
+                    Arg::with_name("example")
+                        .long("example")
+                        .value_name("TEXT")
+                        .help("Text to show the segmentation of, defaults to the first lines")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("train", matches)) => train(matches),
+        Some(("align", matches)) => align(matches),
+        Some(("repl", matches)) => repl(matches),
+        Some(("find", matches)) => find(matches),
+        Some(("coverage", matches)) => coverage(matches),
+        Some(("verify", matches)) => verify(matches),
+        Some(("cost", matches)) => cost(matches),
+        Some(("export", matches)) => export(matches),
+        Some(("decode", matches)) => decode(matches),
+        Some(("pattern-lab", matches)) => pattern_lab(matches),
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Text normalization applied to training inputs before they reach the tokenizer.
+///
+/// The normalizer is not stored in the `.model` file, so text that is encoded with a trained
+/// model should be passed through the same normalizer first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalizer {
+    /// Leave the text untouched.
+    #[default]
+    None,
+    /// Lowercase the text using Unicode case mapping.
+    Lowercase,
+    /// Replace every run of whitespace with a single space.
+    CollapseWhitespace,
+}
+
+impl Normalizer {
+    /// Returns the normalized version of `text`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::normalizer::Normalizer;
+    ///
+    /// assert_eq!(Normalizer::Lowercase.apply("Hello World"), "hello world");
+    /// assert_eq!(Normalizer::CollapseWhitespace.apply("a \n\t b"), "a b");
+    /// ```
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Normalizer::None => text.to_string(),
+            Normalizer::Lowercase => text.to_lowercase(),
+            Normalizer::CollapseWhitespace => {
+                let mut result = String::with_capacity(text.len());
+                let mut in_whitespace = false;
+                for ch in text.chars() {
+                    if ch.is_whitespace() {
+                        if !in_whitespace {
+                            result.push(' ');
+                        }
+                        in_whitespace = true;
+                    } else {
+                        result.push(ch);
+                        in_whitespace = false;
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    /// Returns the name used for this normalizer in config files.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Normalizer::None => "none",
+            Normalizer::Lowercase => "lowercase",
+            Normalizer::CollapseWhitespace => "collapse-whitespace",
+        }
+    }
+}
+
+impl FromStr for Normalizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Normalizer::None),
+            "lowercase" => Ok(Normalizer::Lowercase),
+            "collapse-whitespace" => Ok(Normalizer::CollapseWhitespace),
+            _ => Err(format!(
+                "Unknown normalizer '{}' (expected none, lowercase or collapse-whitespace)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(Normalizer::None.apply("Hello  World"), "Hello  World");
+        assert_eq!(Normalizer::Lowercase.apply("ÀB c"), "àb c");
+        assert_eq!(Normalizer::CollapseWhitespace.apply("  a\n\nb  "), " a b ");
+        assert_eq!(Normalizer::CollapseWhitespace.apply(""), "");
+    }
+
+    #[test]
+    fn test_from_str_roundtrip() {
+        for normalizer in [Normalizer::None, Normalizer::Lowercase, Normalizer::CollapseWhitespace]
+        {
+            assert_eq!(normalizer.as_str().parse::<Normalizer>(), Ok(normalizer));
+        }
+        assert!("nfkc".parse::<Normalizer>().is_err());
+    }
+}
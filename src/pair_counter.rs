@@ -0,0 +1,205 @@
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::HashMap;
+
+const BYTE_IDS: usize = 256;
+
+/// Counts consecutive pairs of token ids during training.
+///
+/// Pairs of two byte ids (both below 256) dominate the first few hundred merges, so they are
+/// counted in a flat 256×256 array that is cheap to update and to scan. Pairs involving merged
+/// tokens go to a hash map. The switch happens per pair, so the counter moves from the dense
+/// array to the map on its own as the vocab grows.
+pub struct PairCounter {
+    dense: Box<[u64]>,
+    dense_pairs: usize,
+    sparse: HashMap<(u32, u32), u64>,
+}
+
+impl PairCounter {
+    pub fn new() -> Self {
+        Self {
+            dense: vec![0; BYTE_IDS * BYTE_IDS].into_boxed_slice(),
+            dense_pairs: 0,
+            sparse: HashMap::default(),
+        }
+    }
+
+    /// Adds `count` occurrences of `pair`.
+    pub fn add(&mut self, pair: (u32, u32), count: u64) {
+        match dense_index(pair) {
+            Some(index) => {
+                if self.dense[index] == 0 && count > 0 {
+                    self.dense_pairs += 1;
+                }
+                self.dense[index] += count;
+            }
+            None => *self.sparse.entry(pair).or_insert(0) += count,
+        }
+    }
+
+    /// Adds every consecutive pair of `ids`, each occurring `weight` times.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::pair_counter::PairCounter;
+    ///
+    /// let mut counter = PairCounter::new();
+    /// counter.add_ids(&[1, 2, 1, 2, 300], 1);
+    /// assert_eq!(counter.get((1, 2)), 2);
+    /// assert_eq!(counter.get((2, 300)), 1);
+    /// assert_eq!(counter.most_frequent(), Some(((1, 2), 2)));
+    /// ```
+    pub fn add_ids(&mut self, ids: &[u32], weight: u64) {
+        for window in ids.windows(2) {
+            self.add((window[0], window[1]), weight);
+        }
+    }
+
+    /// Returns how often `pair` was counted.
+    pub fn get(&self, pair: (u32, u32)) -> u64 {
+        match dense_index(pair) {
+            Some(index) => self.dense[index],
+            None => self.sparse.get(&pair).copied().unwrap_or(0),
+        }
+    }
+
+    /// Returns the number of distinct pairs counted.
+    pub fn len(&self) -> usize {
+        self.dense_pairs + self.sparse.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the most frequent pair and its count. Ties go to the smallest pair, so the result
+    /// does not depend on hash map iteration order.
+    pub fn most_frequent(&self) -> Option<((u32, u32), u64)> {
+        self.most_frequent_where(|_| true)
+    }
+
+    /// Returns the most frequent pair accepted by `allowed`, with ties broken like
+    /// [`PairCounter::most_frequent`]. `allowed` is only called for pairs that would beat the
+    /// best pair found so far.
+    pub fn most_frequent_where<F: FnMut((u32, u32)) -> bool>(
+        &self,
+        mut allowed: F,
+    ) -> Option<((u32, u32), u64)> {
+        let mut best: Option<((u32, u32), u64)> = None;
+        if self.dense_pairs > 0 {
+            // Scanning in index order visits pairs in ascending order, so the first maximum is
+            // also the smallest pair with that count.
+            let mut best_count = 0;
+            for (index, &count) in self.dense.iter().enumerate() {
+                if count > best_count {
+                    let pair = ((index / BYTE_IDS) as u32, (index % BYTE_IDS) as u32);
+                    if allowed(pair) {
+                        best_count = count;
+                        best = Some((pair, count));
+                    }
+                }
+            }
+        }
+        for (&pair, &count) in &self.sparse {
+            if count == 0 {
+                continue;
+            }
+            let better = match best {
+                Some((best_pair, best_count)) => {
+                    count > best_count || (count == best_count && pair < best_pair)
+                }
+                None => true,
+            };
+            if better && allowed(pair) {
+                best = Some((pair, count));
+            }
+        }
+        best
+    }
+
+    /// Resets all counts while keeping the allocations for reuse.
+    pub fn clear(&mut self) {
+        if self.dense_pairs > 0 {
+            self.dense.fill(0);
+            self.dense_pairs = 0;
+        }
+        self.sparse.clear();
+    }
+}
+
+impl Default for PairCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn dense_index((first, second): (u32, u32)) -> Option<usize> {
+    if (first as usize) < BYTE_IDS && (second as usize) < BYTE_IDS {
+        Some(first as usize * BYTE_IDS + second as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::get_stats;
+
+    #[test]
+    fn test_matches_get_stats() {
+        let ids = vec![104, 105, 256, 104, 105, 256, 300, 104, 105];
+        let mut counter = PairCounter::new();
+        counter.add_ids(&ids, 1);
+        let stats = get_stats(&ids);
+        assert_eq!(counter.len(), stats.len());
+        for (&pair, &count) in &stats {
+            assert_eq!(counter.get(pair), count as u64);
+        }
+        assert_eq!(counter.most_frequent(), Some(((104, 105), 3)));
+    }
+
+    #[test]
+    fn test_most_frequent_across_dense_and_sparse() {
+        let mut counter = PairCounter::new();
+        assert_eq!(counter.most_frequent(), None);
+        counter.add((1, 2), 3);
+        counter.add((256, 1), 5);
+        assert_eq!(counter.most_frequent(), Some(((256, 1), 5)));
+        counter.add((0, 9), 5);
+        // Ties go to the smallest pair.
+        assert_eq!(counter.most_frequent(), Some(((0, 9), 5)));
+        counter.add((300, 2), 5);
+        counter.add((257, 2), 5);
+        assert_eq!(counter.most_frequent(), Some(((0, 9), 5)));
+    }
+
+    #[test]
+    fn test_most_frequent_where() {
+        let mut counter = PairCounter::new();
+        counter.add((1, 2), 9);
+        counter.add((3, 4), 7);
+        counter.add((256, 1), 8);
+        counter.add((257, 1), 6);
+        assert_eq!(counter.most_frequent_where(|pair| pair != (1, 2)), Some(((256, 1), 8)));
+        assert_eq!(
+            counter.most_frequent_where(|pair| pair.0 != 256 && pair != (1, 2)),
+            Some(((3, 4), 7))
+        );
+        assert_eq!(counter.most_frequent_where(|_| false), None);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut counter = PairCounter::new();
+        counter.add_ids(&[1, 2, 300, 2], 4);
+        assert_eq!(counter.get((1, 2)), 4);
+        counter.clear();
+        assert!(counter.is_empty());
+        assert_eq!(counter.get((1, 2)), 0);
+        assert_eq!(counter.most_frequent(), None);
+    }
+}
@@ -0,0 +1,217 @@
+//! An interactive prompt for exploring a trained vocab.
+//!
+//! Commands:
+//!
+//! * `:encode <text>` – encode text and show the ids and their tokens (plain input does the same)
+//! * `:decode <ids>` – decode ids separated by spaces or commas
+//! * `:token <id>` – show the bytes of a token
+//! * `:find <substring>` – list tokens containing the substring
+//! * `:history` – list previous commands, `!<n>` runs command `n` again
+//! * `:help` – list the commands
+//! * `:quit` – leave the prompt (end of input works too)
+use std::io::{self, BufRead, Write};
+
+use crate::util::render_token;
+use crate::TokenizerTrait;
+
+const PROMPT: &str = "rbe> ";
+const MAX_FIND_RESULTS: usize = 50;
+const HELP: &str = "\
+:encode <text>       encode text (plain input is encoded too)
+:decode <ids>        decode ids separated by spaces or commas
+:token <id>          show the bytes of a token
+:find <substring>    list tokens containing the substring
+:history             list previous commands, !<n> runs command n again
+:help                show this help
+:quit                leave the prompt";
+
+pub struct Repl<'a> {
+    tokenizer: &'a dyn TokenizerTrait,
+    history: Vec<String>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(tokenizer: &'a dyn TokenizerTrait) -> Self {
+        Self { tokenizer, history: Vec::new() }
+    }
+
+    /// Returns the commands run so far, oldest first.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Reads commands from `input` until `:quit` or end of input, writing a prompt before every
+    /// command and the results to `output`.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => {
+                    writeln!(output)?;
+                    return Ok(());
+                }
+            };
+            let mut command = line.trim_end_matches(['\r', '\n']).to_string();
+            if command.trim().is_empty() {
+                continue;
+            }
+            if let Some(n) = command.strip_prefix('!') {
+                match n
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| self.history.get(n.wrapping_sub(1)))
+                {
+                    Some(previous) => {
+                        command = previous.clone();
+                        writeln!(output, "{}", command)?;
+                    }
+                    None => {
+                        writeln!(output, "error: no command {} in history", n.trim())?;
+                        continue;
+                    }
+                }
+            }
+            if matches!(command.trim(), ":quit" | ":q") {
+                return Ok(());
+            }
+            let result = self.execute(&command);
+            self.history.push(command);
+            match result {
+                Ok(text) => writeln!(output, "{}", text)?,
+                Err(message) => writeln!(output, "error: {}", message)?,
+            }
+        }
+    }
+
+    /// Runs a single command and returns the text to show.
+    pub fn execute(&self, command: &str) -> Result<String, String> {
+        let (name, argument) = match command.strip_prefix(':') {
+            Some(command) => command.split_once(' ').unwrap_or((command, "")),
+            None => ("encode", command),
+        };
+        match name {
+            "encode" | "e" => Ok(self.encode(argument)),
+            "decode" | "d" => self.decode(argument),
+            "token" | "t" => self.token(argument),
+            "find" | "f" => Ok(self.find(argument)),
+            "history" => Ok(self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, command)| format!("{:>4}  {}", i + 1, command))
+                .collect::<Vec<String>>()
+                .join("\n")),
+            "help" | "h" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command ':{}', try :help", name)),
+        }
+    }
+
+    fn render_id(&self, id: u32) -> Option<String> {
+        let tokenizer = self.tokenizer.as_ref();
+        match tokenizer.vocab.get(&id) {
+            Some(token) => Some(render_token(token)),
+            None => tokenizer.inverse_special_tokens.get(&id).cloned(),
+        }
+    }
+
+    fn encode(&self, text: &str) -> String {
+        let ids = self.tokenizer.encode(text);
+        let pieces: Vec<String> =
+            ids.iter().map(|&id| format!("[{}]", self.render_id(id).unwrap_or_default())).collect();
+        format!("{:?}\n{} tokens: {}", ids, ids.len(), pieces.join(" "))
+    }
+
+    fn decode(&self, argument: &str) -> Result<String, String> {
+        let ids = argument
+            .split(|ch: char| ch == ',' || ch.is_whitespace())
+            .filter(|id| !id.is_empty())
+            .map(|id| id.parse::<u32>().map_err(|_| format!("'{}' is not a token id", id)))
+            .collect::<Result<Vec<u32>, String>>()?;
+        let text = self.tokenizer.decode_strict(&ids).map_err(|e| e.to_string())?;
+        Ok(format!("{:?}", text))
+    }
+
+    fn token(&self, argument: &str) -> Result<String, String> {
+        let id = argument
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("'{}' is not a token id", argument.trim()))?;
+        let tokenizer = self.tokenizer.as_ref();
+        if let Some(token) = tokenizer.vocab.get(&id) {
+            Ok(format!("{} [{}] {} bytes {:?}", id, render_token(token), token.len(), token))
+        } else if let Some(special) = tokenizer.inverse_special_tokens.get(&id) {
+            Ok(format!("{} [{}] special token", id, special))
+        } else {
+            Err(format!("no token with id {}", id))
+        }
+    }
+
+    fn find(&self, needle: &str) -> String {
+        let found = self.tokenizer.as_ref().find_tokens_containing(needle.as_bytes());
+        let mut lines: Vec<String> = found
+            .iter()
+            .take(MAX_FIND_RESULTS)
+            .map(|&(id, token)| format!("{:>6} [{}]", id, render_token(token)))
+            .collect();
+        if found.len() > MAX_FIND_RESULTS {
+            lines.push(format!("... {} more", found.len() - MAX_FIND_RESULTS));
+        }
+        lines.push(format!("{} tokens match", found.len()));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizers::basic::Tokenizer;
+
+    fn tokenizer() -> Tokenizer {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("hello hello hello", 258, false);
+        tokenizer
+    }
+
+    #[test]
+    fn test_execute() {
+        let tokenizer = tokenizer();
+        let repl = Repl::new(&tokenizer);
+        assert_eq!(repl.execute(":encode helo").unwrap(), "[257, 111]\n2 tokens: [hel] [o]");
+        assert_eq!(repl.execute("helo").unwrap(), repl.execute(":encode helo").unwrap());
+        assert_eq!(repl.execute(":decode 257, 108 111").unwrap(), "\"hello\"");
+        assert_eq!(repl.execute(":token 256").unwrap(), "256 [el] 2 bytes [101, 108]");
+        assert_eq!(repl.execute(":find el").unwrap(), "   256 [el]\n   257 [hel]\n2 tokens match");
+        assert!(repl.execute(":token 999").is_err());
+        assert!(repl.execute(":decode x").is_err());
+        assert_eq!(
+            repl.execute(":decode 104 9999").unwrap_err(),
+            "unknown token id 9999 at position 1"
+        );
+        assert!(repl.execute(":nope").is_err());
+    }
+
+    #[test]
+    fn test_run_with_history() -> io::Result<()> {
+        let tokenizer = tokenizer();
+        let mut repl = Repl::new(&tokenizer);
+        let input = b":token 104\n\n:history\n!1\n!9\n:quit\n:token 105\n";
+        let mut output = Vec::new();
+        repl.run(&input[..], &mut output)?;
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "rbe> 104 [h] 1 bytes [104]\n\
+             rbe> rbe>    1  :token 104\n\
+             rbe> :token 104\n104 [h] 1 bytes [104]\n\
+             rbe> error: no command 9 in history\n\
+             rbe> "
+        );
+        assert_eq!(repl.history(), [":token 104", ":history", ":token 104"]);
+        Ok(())
+    }
+}
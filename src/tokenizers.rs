@@ -1,2 +1,6 @@
 pub mod basic;
 pub mod regex;
+#[cfg(feature = "std")]
+pub mod sentencepiece;
+#[cfg(feature = "std")]
+pub mod tiktoken;
@@ -1,66 +1,261 @@
-use std::collections::HashMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
 
-use crate::util::{get_stats, merge, render_token};
-use crate::TokenizerTrait;
+use crate::pair_counter::PairCounter;
+use crate::train::{MergeStep, TrainOptions};
+#[cfg(feature = "std")]
+use crate::util::render_token;
+use crate::util::{merge, merge_in_place};
+use crate::{EncodeScratch, HashMap, ModelError, TokenizerTrait};
 
 pub struct Tokenizer {
+    /// The id of the token every merge creates. Add merges with [`Tokenizer::add_merge`], or
+    /// call [`Tokenizer::build_vocab`] after changing this map directly.
     pub merges: HashMap<(u32, u32), u32>,
     pub vocab: HashMap<u32, Vec<u8>>,
     pub pattern: String,
+    pub special_tokens: HashMap<String, u32>,
+    pub inverse_special_tokens: HashMap<u32, String>,
+    /// What every merge of the last training run did to the corpus, if
+    /// [`TrainOptions::record_curve`] was set.
+    pub growth_curve: Vec<MergeStep>,
+    /// The merges in the order they were learned, which is also increasing id order.
+    merge_order: Vec<((u32, u32), u32)>,
 }
 
 impl Tokenizer {
     pub fn new() -> Self {
         Self {
-            merges: HashMap::new(),
+            merges: HashMap::default(),
             vocab: (0..256).map(|idx| (idx, vec![idx as u8])).collect(),
             pattern: String::new(),
+            special_tokens: HashMap::default(),
+            inverse_special_tokens: HashMap::default(),
+            growth_curve: Vec::new(),
+            merge_order: Vec::new(),
         }
     }
 
+    /// Registers special tokens, replacing any previously registered ones.
+    ///
+    /// Special tokens are never produced by `encode`; they only exist so that `decode` can
+    /// render them and so that they are persisted alongside the merges.
+    pub fn register_special_tokens(&mut self, special_tokens: HashMap<String, u32>) {
+        self.inverse_special_tokens =
+            special_tokens.iter().map(|(token, &idx)| (idx, token.clone())).collect();
+        self.special_tokens = special_tokens;
+    }
+
+    /// Adds a merge of `pair` into the new token `idx`, after all existing merges.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is not larger than the ids of the existing merges, or if a part of `pair`
+    /// is not in the vocab.
+    pub fn add_merge(&mut self, pair: (u32, u32), idx: u32) {
+        assert!(
+            self.merge_order.last().is_none_or(|&(_, last)| last < idx),
+            "merge {} added after a merge with a higher id",
+            idx
+        );
+        let token = self.merged_token(pair);
+        self.merges.insert(pair, idx);
+        self.merge_order.push((pair, idx));
+        self.vocab.insert(idx, token);
+    }
+
+    /// Drops all merges and the last growth curve, leaving only the byte tokens in the vocab,
+    /// so that training starts from scratch. Special tokens are kept.
+    pub(crate) fn clear_merges(&mut self) {
+        self.merges.clear();
+        self.merge_order.clear();
+        self.vocab = (0..256).map(|idx| (idx, vec![idx as u8])).collect();
+        self.growth_curve.clear();
+    }
+
+    /// Rebuilds the merge order and the vocab from the byte tokens and `merges`, giving every
+    /// merged token the id stored in `merges`.
     pub fn build_vocab(&mut self) {
+        self.merge_order = self.merges.iter().map(|(&pair, &idx)| (pair, idx)).collect();
+        self.merge_order.sort_unstable_by_key(|&(_, idx)| idx);
+
         self.vocab.clear();
         for idx in 0..256 {
             self.vocab.insert(idx, vec![idx as u8]);
         }
-
-        let mut next_idx = 256;
-        for &(idx1, idx2) in self.merges.keys() {
+        // Parts always have lower ids than the tokens merged from them.
+        for &((idx1, idx2), idx) in &self.merge_order {
             if let (Some(token1), Some(token2)) = (self.vocab.get(&idx1), self.vocab.get(&idx2)) {
-                let mut new_token = token1.clone();
-                new_token.extend(token2);
-                self.vocab.insert(next_idx, new_token);
-                next_idx += 1;
+                let new_token = [token1.as_slice(), token2.as_slice()].concat();
+                self.vocab.insert(idx, new_token);
             }
         }
     }
 
+    /// Returns the merges, as pair and id of the merged token, in the order they were learned.
+    /// Encoding applies them in this order, and it is the order `save` writes them in. Use this
+    /// instead of iterating `merges`, whose order differs between runs. Merges inserted into
+    /// `merges` directly only show up here after [`Tokenizer::build_vocab`].
+    pub fn merges_in_order(&self) -> impl Iterator<Item = ((u32, u32), u32)> + '_ {
+        debug_assert!(self.merge_order_is_current(), "merges changed without build_vocab");
+        self.merge_order.iter().copied()
+    }
+
+    /// Returns whether the merge order holds exactly the merges in `merges`, which is false
+    /// after `merges` was changed directly.
+    fn merge_order_is_current(&self) -> bool {
+        self.merge_order.len() == self.merges.len()
+            && self.merge_order.iter().all(|(pair, idx)| self.merges.get(pair) == Some(idx))
+    }
+
+    /// Returns the position of the merge that created token `id` in the merge order, 0 for the
+    /// first merge. Byte tokens, special tokens and unknown ids have no rank.
+    pub fn rank_of(&self, id: u32) -> Option<u32> {
+        self.merge_order.binary_search_by_key(&id, |&(_, idx)| idx).ok().map(|rank| rank as u32)
+    }
+
+    /// Returns the bytes `id` decodes to: the vocab entry, or the text of a special token.
+    pub fn token_bytes(&self, id: u32) -> Option<&[u8]> {
+        self.vocab
+            .get(&id)
+            .map(|bytes| bytes.as_slice())
+            .or_else(|| self.inverse_special_tokens.get(&id).map(|token| token.as_bytes()))
+    }
+
+    /// Returns the bytes of the token that merging `pair` would create.
+    pub fn merged_token(&self, pair: (u32, u32)) -> Vec<u8> {
+        [self.vocab[&pair.0].as_slice(), self.vocab[&pair.1].as_slice()].concat()
+    }
+
+    /// Encodes raw bytes, which unlike `encode` need not be valid UTF-8.
+    pub fn encode_bytes(&self, text_bytes: &[u8]) -> Vec<u32> {
+        let mut ids = Vec::new();
+        self.encode_bytes_into(text_bytes, &mut Vec::new(), &mut ids);
+        ids
+    }
+
+    /// Encodes `text_bytes` as a single chunk and appends the ids to `out`, using `work` as the
+    /// buffer for the merges.
+    ///
+    /// Merges are applied in the order they were learned: the pair with the lowest merge id is
+    /// replaced everywhere, then the next one, until no pair has a merge.
+    pub(crate) fn encode_bytes_into(
+        &self,
+        text_bytes: &[u8],
+        work: &mut Vec<u32>,
+        out: &mut Vec<u32>,
+    ) {
+        work.clear();
+        work.extend(text_bytes.iter().map(|&b| b as u32));
+        while work.len() >= 2 {
+            let best = work
+                .windows(2)
+                .filter_map(|pair| {
+                    let pair = (pair[0], pair[1]);
+                    self.merges.get(&pair).map(|&idx| (idx, pair))
+                })
+                .min_by_key(|&(idx, _)| idx);
+            match best {
+                Some((idx, pair)) => merge_in_place(work, pair, idx),
+                None => break,
+            }
+        }
+        out.extend_from_slice(work);
+    }
+
+    /// Returns the tokens whose bytes satisfy `predicate`, ordered by id.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::tokenizers::basic::Tokenizer;
+    ///
+    /// let tokenizer = Tokenizer::new();
+    /// let digits = tokenizer.find_tokens(|token| token.iter().all(u8::is_ascii_digit));
+    /// assert_eq!(digits.len(), 10);
+    /// assert_eq!(digits[0], (48, &b"0"[..]));
+    /// ```
+    pub fn find_tokens<F: Fn(&[u8]) -> bool>(&self, predicate: F) -> Vec<(u32, &[u8])> {
+        let mut found: Vec<(u32, &[u8])> = self
+            .vocab
+            .iter()
+            .filter(|(_, token)| predicate(token))
+            .map(|(&id, token)| (id, token.as_slice()))
+            .collect();
+        found.sort_unstable_by_key(|&(id, _)| id);
+        found
+    }
+
+    /// Returns the tokens containing `needle` as a byte substring, ordered by id.
+    pub fn find_tokens_containing(&self, needle: &[u8]) -> Vec<(u32, &[u8])> {
+        self.find_tokens(|token| {
+            needle.is_empty() || token.windows(needle.len()).any(|window| window == needle)
+        })
+    }
+
+    /// Returns the tokens whose bytes match `pattern`, ordered by id. The pattern runs on the
+    /// raw bytes, so `é` finds tokens containing that character, and `(?-u:\xFF)` finds tokens
+    /// containing that byte.
+    pub fn find_tokens_matching(&self, pattern: &regex::bytes::Regex) -> Vec<(u32, &[u8])> {
+        self.find_tokens(|token| pattern.is_match(token))
+    }
+
+    /// Returns the most frequent pair in `stats`, preferring the smallest pair on ties.
     pub fn find_most_frequent_pair(&self, stats: &HashMap<(u32, u32), u32>) -> Option<(u32, u32)> {
-        stats.iter().max_by_key(|&(_, &count)| count).map(|(&pair, _)| pair)
+        stats
+            .iter()
+            .max_by_key(|&(&pair, &count)| (count, core::cmp::Reverse(pair)))
+            .map(|(&pair, _)| pair)
+    }
+}
+
+impl AsRef<Tokenizer> for Tokenizer {
+    fn as_ref(&self) -> &Tokenizer {
+        self
+    }
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl TokenizerTrait for Tokenizer {
-    fn train(&mut self, text: &str, vocab_size: u32, verbose: bool) {
-        assert!(vocab_size >= 256);
-        let num_merges = vocab_size - 256;
+    fn train_with_options(&mut self, text: &str, options: &TrainOptions) {
+        assert!(options.vocab_size >= 256);
+        let num_merges = options.vocab_size - 256;
         let text_bytes = text.as_bytes();
         let mut ids: Vec<u32> = text_bytes.iter().map(|&b| b as u32).collect();
+        let mut stats = PairCounter::new();
+        self.clear_merges();
 
         for i in 0..num_merges {
-            let stats = get_stats(&ids);
-            if let Some(pair) = self.find_most_frequent_pair(&stats) {
-                let idx = 256 + i as u32;
+            stats.clear();
+            stats.add_ids(&ids, 1);
+            if let Some((pair, count)) =
+                stats.most_frequent_where(|pair| options.allows(&self.merged_token(pair)))
+            {
+                let idx = 256 + i;
+                let tokens_before = ids.len();
                 ids = merge(ids, pair, idx);
-                self.merges.insert(pair, idx);
-                self.vocab.insert(
-                    idx,
-                    [self.vocab[&pair.0].clone(), self.vocab[&pair.1].clone()].concat(),
-                );
+                let step = MergeStep::new(idx, pair, count, tokens_before, ids.len());
+                if !options.keeps_gain(step.gain) {
+                    break;
+                }
+                if options.record_curve {
+                    self.growth_curve.push(step);
+                }
+                self.add_merge(pair, idx);
 
-                if verbose {
+                #[cfg(feature = "std")]
+                if options.verbose {
                     println!(
                         "merge {}/{}: {:?} -> {} ({:?}) had {} occurances",
                         i + 1,
@@ -71,54 +266,57 @@ impl TokenizerTrait for Tokenizer {
                             [self.vocab[&pair.0].clone(), self.vocab[&pair.1].clone()].concat()
                         )
                         .unwrap_or_else(|_| "Invalid utf-8".to_string()),
-                        stats[&pair]
+                        count
                     );
                 }
-            }
-        }
-    }
-
-    fn encode(&self, text: &str) -> Vec<u32> {
-        let text_bytes = text.as_bytes();
-        let mut ids: Vec<u32> = text_bytes.iter().map(|&b| b as u32).collect();
-        while ids.len() >= 2 {
-            let stats = get_stats(&ids);
-            if let Some((&pair, _)) =
-                stats.iter().min_by_key(|&(&pair, _)| self.merges.get(&pair).unwrap_or(&u32::MAX))
-            {
-                if let Some(&idx) = self.merges.get(&pair) {
-                    ids = merge(ids, pair, idx);
-                } else {
-                    break;
-                }
             } else {
                 break;
             }
         }
-        ids
+    }
+
+    fn encode_into(&self, text: &str, scratch: &mut EncodeScratch, out: &mut Vec<u32>) {
+        self.encode_bytes_into(text.as_bytes(), &mut scratch.ids, out);
     }
 
     fn decode(&self, ids: &[u32]) -> String {
         let text_bytes: Vec<u8> = ids
             .iter()
-            .filter_map(|&id| self.vocab.get(&id))
+            .filter_map(|&id| self.token_bytes(id))
             .flat_map(|bytes| bytes.iter().cloned())
             .collect();
         String::from_utf8(text_bytes).unwrap_or_else(|e| format!("Error decoding text: {:?}", e))
     }
 
+    #[cfg(feature = "std")]
     fn save(&self, file_prefix: &str) -> io::Result<()> {
+        if !self.merge_order_is_current() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Merges were changed directly, call build_vocab before saving",
+            ));
+        }
         let model_file_path = format!("{}.model", file_prefix);
         let vocab_file_path = format!("{}.vocab", file_prefix);
 
         let mut model_file = File::create(model_file_path)?;
         writeln!(model_file, "{}", self.pattern)?;
-        for (&(idx1, idx2), _) in &self.merges {
+        writeln!(model_file, "{}", self.special_tokens.len())?;
+        let mut special_tokens: Vec<(&String, &u32)> = self.special_tokens.iter().collect();
+        special_tokens.sort_by_key(|&(_, &idx)| idx);
+        for (token, idx) in special_tokens {
+            writeln!(model_file, "{} {}", token, idx)?;
+        }
+        // Merges are written in the order they were learned, which is how `load` numbers them,
+        // and everything else is sorted too, so the same model always produces the same files.
+        for ((idx1, idx2), _) in self.merges_in_order() {
             writeln!(model_file, "{} {}", idx1, idx2)?;
         }
 
+        let mut vocab: Vec<(&u32, &Vec<u8>)> = self.vocab.iter().collect();
+        vocab.sort_unstable_by_key(|&(&idx, _)| idx);
         let mut vocab_file = File::create(vocab_file_path)?;
-        for (&idx, token) in &self.vocab {
+        for (idx, token) in vocab {
             let token_string = render_token(token);
             writeln!(vocab_file, "{} [{}]", idx, token_string)?;
         }
@@ -126,21 +324,38 @@ impl TokenizerTrait for Tokenizer {
         Ok(())
     }
 
-    fn load(&mut self, model_file: &str) -> io::Result<()> {
-        assert!(model_file.ends_with(".model"));
-        let file = File::open(model_file)?;
-        let reader = BufReader::new(file);
-
-        let mut lines = reader.lines();
+    fn load_bytes(&mut self, model: &[u8]) -> Result<(), ModelError> {
+        let model =
+            core::str::from_utf8(model).map_err(|_| ModelError::new("Model is not valid UTF-8"))?;
+        let mut lines = model.lines().peekable();
 
         if let Some(first_line) = lines.next() {
-            self.pattern = first_line?.trim().to_string();
+            self.pattern = first_line.trim().to_string();
+        }
+
+        // Models written before special tokens were supported go straight to the merges, so the
+        // special token count is only present when the line holds a single number.
+        let mut special_tokens = HashMap::default();
+        if let Some(num_special) = lines.peek().and_then(|line| line.trim().parse::<usize>().ok()) {
+            lines.next();
+            for _ in 0..num_special {
+                let line =
+                    lines.next().ok_or_else(|| ModelError::new("Missing special token line"))?;
+                let parsed = line
+                    .rsplit_once(' ')
+                    .and_then(|(token, idx)| Some((token.to_string(), idx.parse::<u32>().ok()?)));
+                let (token, idx) = parsed.ok_or_else(|| {
+                    ModelError::new(format!("Invalid special token line: {}", line))
+                })?;
+                special_tokens.insert(token, idx);
+            }
         }
-        let mut merges = HashMap::new();
+        self.register_special_tokens(special_tokens);
+
+        let mut merges = HashMap::default();
         let mut idx = 256;
 
         for line in lines {
-            let line = line?;
             let parts: Vec<&str> = line.split_whitespace().collect();
             if parts.len() == 2 {
                 if let (Ok(idx1), Ok(idx2)) = (parts[0].parse::<u32>(), parts[1].parse::<u32>()) {
@@ -159,12 +374,17 @@ impl TokenizerTrait for Tokenizer {
 mod tests {
 
     use super::*;
+    use crate::UnknownId;
     use tempfile::tempdir;
 
     fn create_temp_tokenizer() -> Tokenizer {
         let mut tokenizer = Tokenizer::new();
-        tokenizer.merges.insert((1, 2), 256);
+        tokenizer.add_merge((1, 2), 256);
         tokenizer.pattern = "some pattern".into();
+        tokenizer.register_special_tokens(HashMap::from([
+            ("<|endoftext|>".to_string(), 257),
+            ("<|fim prefix|>".to_string(), 258),
+        ]));
         tokenizer
     }
 
@@ -195,10 +415,226 @@ mod tests {
         load_tokenizer.load(file_prefix.with_extension("model").to_str().unwrap()).unwrap();
         assert_eq!(load_tokenizer.merges, tokenizer.merges);
         assert_eq!(load_tokenizer.pattern, tokenizer.pattern);
+        assert_eq!(load_tokenizer.special_tokens, tokenizer.special_tokens);
+        assert_eq!(load_tokenizer.decode(&[104, 105, 257]), "hi<|endoftext|>");
 
         Ok(())
     }
 
+    #[test]
+    fn test_save_is_deterministic() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let text = std::fs::read_to_string("data/taylorswift.txt")?;
+        let prefixes: Vec<String> = ["first", "second", "reloaded"]
+            .iter()
+            .map(|name| temp_dir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+
+        // Every tokenizer gets its own hash map seeds, so this catches order dependence.
+        for prefix in &prefixes[..2] {
+            let mut tokenizer = Tokenizer::new();
+            tokenizer.train(&text[..5_000], 300, false);
+            tokenizer.save(prefix)?;
+        }
+        let mut reloaded = Tokenizer::new();
+        reloaded.load(&format!("{}.model", prefixes[0]))?;
+        reloaded.save(&prefixes[2])?;
+
+        for extension in ["model", "vocab"] {
+            let contents: Vec<Vec<u8>> = prefixes
+                .iter()
+                .map(|prefix| std::fs::read(format!("{}.{}", prefix, extension)))
+                .collect::<io::Result<_>>()?;
+            assert_eq!(contents[0], contents[1]);
+            assert_eq!(contents[0], contents[2]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_training_test_vector() {
+        // Exact merges expected on every platform. Ties go to the smallest pair: (97, 98) beats
+        // (256, 97) for the second merge, (97, 99) beats (100, 258) for the fourth.
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("aaabdaaabac aaabdaaabac", 262, false);
+        assert_eq!(
+            tokenizer.merges_in_order().collect::<Vec<_>>(),
+            vec![
+                ((97, 97), 256),
+                ((97, 98), 257),
+                ((256, 257), 258),
+                ((97, 99), 259),
+                ((100, 258), 260),
+                ((258, 260), 261),
+            ]
+        );
+        assert_eq!(tokenizer.vocab[&261], b"aaabdaaab");
+    }
+
+    #[test]
+    fn test_retrain_starts_from_scratch() {
+        let mut once = Tokenizer::new();
+        once.train("aaabdaaabac aaabdaaabac", 262, false);
+        let mut twice = Tokenizer::new();
+        twice.train("hello hello world", 270, false);
+        twice.train("aaabdaaabac aaabdaaabac", 262, false);
+        assert!(twice.merges_in_order().eq(once.merges_in_order()));
+        assert_eq!(twice.merges, once.merges);
+        assert_eq!(twice.vocab, once.vocab);
+    }
+
+    #[test]
+    fn test_merge_order_and_rank() -> io::Result<()> {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("aaabdaaabac aaabdaaabac", 262, false);
+        tokenizer.register_special_tokens(HashMap::from([("<|end|>".to_string(), 262)]));
+        assert_eq!(tokenizer.rank_of(256), Some(0));
+        assert_eq!(tokenizer.rank_of(261), Some(5));
+        assert_eq!(tokenizer.rank_of(97), None);
+        assert_eq!(tokenizer.rank_of(262), None);
+
+        let temp_dir = tempdir()?;
+        let prefix = temp_dir.path().join("ordered");
+        let prefix = prefix.to_str().unwrap();
+        tokenizer.save(prefix)?;
+        let mut loaded = Tokenizer::new();
+        loaded.load(&format!("{}.model", prefix))?;
+        assert!(loaded.merges_in_order().eq(tokenizer.merges_in_order()));
+
+        // Merges inserted directly are only picked up by `build_vocab`.
+        loaded.merges.insert((261, 99), 262);
+        assert!(loaded.save(prefix).is_err());
+        loaded.build_vocab();
+        loaded.save(prefix)?;
+        assert_eq!(loaded.merges_in_order().last(), Some(((261, 99), 262)));
+        assert_eq!(loaded.rank_of(262), Some(6));
+
+        // Replacing a merge keeps the number of merges, which is caught all the same.
+        loaded.merges.remove(&(261, 99));
+        loaded.merges.insert((97, 100), 262);
+        assert!(loaded.save(prefix).is_err());
+        loaded.build_vocab();
+        assert_eq!(loaded.merges_in_order().last(), Some(((97, 100), 262)));
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "merge 256 added after a merge with a higher id")]
+    fn test_add_merge_out_of_order() {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.add_merge((97, 98), 257);
+        tokenizer.add_merge((98, 99), 256);
+    }
+
+    #[test]
+    fn test_load_bytes() {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.load_bytes(b"\n1\n<|end|> 258\n104 105\n256 33\n").unwrap();
+        assert_eq!(tokenizer.special_tokens.get("<|end|>"), Some(&258));
+        assert_eq!(tokenizer.vocab[&257], b"hi!");
+        assert_eq!(tokenizer.decode_strict(&[257, 258]).unwrap(), "hi!<|end|>");
+
+        assert_eq!(
+            tokenizer.load_bytes(b"\n2\n<|end|> 258\n"),
+            Err(ModelError::new("Missing special token line"))
+        );
+        assert_eq!(
+            tokenizer.load_bytes(b"\n\xff\xfe\n"),
+            Err(ModelError::new("Model is not valid UTF-8"))
+        );
+    }
+
+    #[test]
+    fn test_load_without_special_tokens() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let model_path = temp_dir.path().join("legacy.model");
+        std::fs::write(&model_path, "\n104 105\n")?;
+
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.load(model_path.to_str().unwrap())?;
+        assert!(tokenizer.special_tokens.is_empty());
+        assert_eq!(tokenizer.merges.get(&(104, 105)), Some(&256));
+        Ok(())
+    }
+
+    #[test]
+    fn test_train_with_options() {
+        let text = "the cat sat on the mat the cat sat on the mat";
+
+        let mut unconstrained = Tokenizer::new();
+        unconstrained.train(text, 270, false);
+        assert!(unconstrained
+            .vocab
+            .values()
+            .any(|token| token.contains(&b' ')
+                && token.windows(2).any(|w| w[0] != b' ' && w[1] == b' ')));
+
+        let mut tokenizer = Tokenizer::new();
+        let options = TrainOptions {
+            max_token_len: Some(3),
+            whitespace_guard: true,
+            ..TrainOptions::new(270)
+        };
+        tokenizer.train_with_options(text, &options);
+        assert!(!tokenizer.merges.is_empty());
+        for token in tokenizer.vocab.values() {
+            assert!(options.allows(token), "learned forbidden token {:?}", token);
+        }
+        assert_eq!(tokenizer.decode(&tokenizer.encode(text)), text);
+    }
+
+    #[test]
+    fn test_growth_curve_and_min_gain() {
+        let text = std::fs::read_to_string("data/taylorswift.txt").unwrap();
+        let text = &text[..5_000];
+        let mut tokenizer = Tokenizer::new();
+        let options = TrainOptions { record_curve: true, ..TrainOptions::new(300) };
+        tokenizer.train_with_options(text, &options);
+        let curve = tokenizer.growth_curve.clone();
+        assert_eq!(curve.len(), 44);
+        assert_eq!(curve[0].id, 256);
+        assert_eq!(curve[0].tokens, text.len() - curve[0].frequency as usize);
+        assert_eq!(curve[43].tokens, tokenizer.encode(text).len());
+        assert!(curve.windows(2).all(|w| w[1].tokens < w[0].tokens));
+
+        // Training stops before the first merge whose gain falls below the threshold.
+        let first_below = curve.iter().position(|step| step.gain < 0.005).unwrap();
+        assert!(first_below > 0);
+        let mut stopped = Tokenizer::new();
+        stopped.train_with_options(
+            text,
+            &TrainOptions { min_gain: Some(0.005), ..TrainOptions::new(300) },
+        );
+        assert_eq!(stopped.merges.len(), first_below);
+        assert!(stopped.growth_curve.is_empty());
+    }
+
+    #[test]
+    fn test_find_tokens() {
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("swift swift swift", 260, false);
+        let swift = tokenizer.find_tokens_containing(b"swift");
+        assert_eq!(swift.len(), 1);
+        assert_eq!(swift[0].1, b"swift");
+
+        let found = tokenizer.find_tokens_containing(b"wi");
+        assert!(found.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(found.iter().all(|(_, token)| token.windows(2).any(|w| w == b"wi")));
+
+        let pattern = regex::bytes::Regex::new(r"^[\x00-\x02]$").unwrap();
+        let control: Vec<u32> =
+            tokenizer.find_tokens_matching(&pattern).iter().map(|&(id, _)| id).collect();
+        assert_eq!(control, vec![0, 1, 2]);
+
+        // Multi-byte characters match as text, not as their rendered bytes.
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.train("café café café", 262, false);
+        let pattern = regex::bytes::Regex::new(r"^\w*é$").unwrap();
+        let found = tokenizer.find_tokens_matching(&pattern);
+        assert!(found.iter().any(|&(_, token)| token == "café".as_bytes()));
+        assert!(found.iter().all(|&(_, token)| token.ends_with("é".as_bytes())));
+    }
+
     #[test]
     fn test_encode_decode() {
         let test_strings = ["", "?", "hello world!!!? (안녕하세요!) lol123 😉"];
@@ -209,4 +645,21 @@ mod tests {
             assert_eq!(test_string, decoded);
         }
     }
+
+    #[test]
+    fn test_decode_unknown_ids() {
+        let mut tokenizer = create_temp_tokenizer();
+        tokenizer.build_vocab();
+        let ids = [104, 105, 300, 257, 301];
+        assert_eq!(tokenizer.decode(&ids), "hi<|endoftext|>");
+        assert_eq!(tokenizer.decode_strict(&[104, 257]).unwrap(), "h<|endoftext|>");
+        assert_eq!(tokenizer.decode_strict(&ids), Err(UnknownId { id: 300, position: 2 }));
+
+        let (text, unknown) = tokenizer.decode_with_placeholder(&ids, "<?>");
+        assert_eq!(text, "hi<?><|endoftext|><?>");
+        assert_eq!(
+            unknown,
+            vec![UnknownId { id: 300, position: 2 }, UnknownId { id: 301, position: 4 }]
+        );
+    }
 }
@@ -1,87 +1,322 @@
-use std::collections::HashMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::sync::{LazyLock, Mutex};
+#[cfg(feature = "std")]
+use std::{fs, io, thread};
 
 use crate::{
+    pair_counter::PairCounter,
     tokenizers::basic::Tokenizer,
-    util::{get_stats, merge},
-    TokenizerTrait,
+    train::{MergeStep, TrainOptions},
+    util::merge,
+    EncodeScratch, HashMap, ModelError, TokenizerTrait,
 };
 
 use regex::Regex;
 
-const GPT4_SPLIT_PATTERN: &str = r#"'(?i:[sdmt]|ll|ve|re)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]|\s+"#;
+/// The split pattern of GPT-4's cl100k_base encoding, without its `\s+(?!\S)` alternative and
+/// possessive quantifiers, which the `regex` crate does not support.
+pub const GPT4_SPLIT_PATTERN: &str = r#"'(?i:[sdmt]|ll|ve|re)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]|\s+"#;
+
+/// Split patterns compiled so far, shared by all tokenizers.
+#[cfg(feature = "std")]
+static PATTERN_CACHE: LazyLock<Mutex<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+
+/// Compiles `pattern`, reusing the regex compiled by an earlier call with the same pattern so
+/// that creating many tokenizers only compiles it once. Clones of a `Regex` share the compiled
+/// program. Without the `std` feature there is no cache and every call compiles.
+///
+/// Entries are never evicted, so only patterns chosen in code go through here. Patterns read from
+/// model files use [`compile_model_pattern`].
+fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    #[cfg(feature = "std")]
+    {
+        let mut cache = PATTERN_CACHE.lock().unwrap();
+        if let Some(regex) = cache.get(pattern) {
+            return Ok(regex.clone());
+        }
+        let regex = Regex::new(pattern)?;
+        cache.insert(pattern.to_string(), regex.clone());
+        Ok(regex)
+    }
+    #[cfg(not(feature = "std"))]
+    Regex::new(pattern)
+}
+
+/// Compiles a split pattern that came with a model. Only the GPT-4 pattern is shared through the
+/// cache, so that loading many models with their own patterns does not grow it.
+fn compile_model_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    if pattern == GPT4_SPLIT_PATTERN {
+        compile_pattern(pattern)
+    } else {
+        Regex::new(pattern)
+    }
+}
 
 pub struct RegexTokenizer {
-    tokenizer: Tokenizer,
+    pub(crate) tokenizer: Tokenizer,
     compiled_pattern: Regex,
+    /// Alternation of the registered special tokens, longest first, used by
+    /// `encode_with_special`. `None` when there are no special tokens.
+    special_pattern: Option<Regex>,
+}
+
+/// Compiles an alternation of `special_tokens`, preferring the longest special token when
+/// several start at the same position.
+fn special_pattern(special_tokens: &HashMap<String, u32>) -> Option<Regex> {
+    if special_tokens.is_empty() {
+        return None;
+    }
+    let mut special: Vec<&String> = special_tokens.keys().collect();
+    special.sort_by_key(|token| core::cmp::Reverse(token.len()));
+    let alternation: Vec<String> = special.iter().map(|token| regex::escape(token)).collect();
+    Some(Regex::new(&alternation.join("|")).unwrap())
 }
 
 impl RegexTokenizer {
     pub fn new() -> Self {
         let mut tokenizer = Tokenizer::new();
         tokenizer.pattern = GPT4_SPLIT_PATTERN.to_string();
-        let compiled_pattern = Regex::new(GPT4_SPLIT_PATTERN).unwrap();
+        let compiled_pattern = compile_pattern(GPT4_SPLIT_PATTERN).unwrap();
 
-        RegexTokenizer { tokenizer, compiled_pattern }
+        RegexTokenizer { tokenizer, compiled_pattern, special_pattern: None }
     }
 
-    pub fn encode_chunk(&self, chunk: &str) -> Vec<u32> {
-        let mut chunk_ids: Vec<u32> = chunk.bytes().map(|m| m as u32).collect();
-        let mut i = 0;
-        while i + 1 < chunk_ids.len() {
-            let pair = (chunk_ids[i], chunk_ids[i + 1]);
-            if let Some(&new_id) = self.tokenizer.merges.get(&pair) {
-                chunk_ids[i] = new_id; // Replace the pair with the new_id
-                chunk_ids.remove(i + 1); // Remove the second part of the pair
-                                         // Do not increment i, to check the new pair formed with the new_id
-            } else {
-                i += 1;
+    /// Creates a tokenizer that pre-tokenizes text with `pattern` instead of the GPT-4 pattern.
+    pub fn with_pattern(pattern: &str) -> Result<Self, regex::Error> {
+        let compiled_pattern = compile_pattern(pattern)?;
+        let mut tokenizer = Tokenizer::new();
+        tokenizer.pattern = pattern.to_string();
+
+        Ok(RegexTokenizer { tokenizer, compiled_pattern, special_pattern: None })
+    }
+
+    /// Wraps an already trained or loaded tokenizer, compiling its split pattern.
+    pub fn from_tokenizer(tokenizer: Tokenizer) -> Result<Self, regex::Error> {
+        let compiled_pattern = compile_model_pattern(&tokenizer.pattern)?;
+        let special_pattern = special_pattern(&tokenizer.special_tokens);
+        Ok(RegexTokenizer { tokenizer, compiled_pattern, special_pattern })
+    }
+
+    /// Returns the underlying byte-level tokenizer holding the merges and vocab.
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    /// Registers special tokens, see [`Tokenizer::register_special_tokens`].
+    pub fn register_special_tokens(&mut self, special_tokens: HashMap<String, u32>) {
+        self.special_pattern = special_pattern(&special_tokens);
+        self.tokenizer.register_special_tokens(special_tokens);
+    }
+
+    /// Encodes `text` like `encode`, but emits the id of every registered special token that
+    /// appears verbatim in the text instead of encoding its bytes.
+    pub fn encode_with_special(&self, text: &str) -> Vec<u32> {
+        let Some(special_pattern) = &self.special_pattern else {
+            return self.encode(text);
+        };
+
+        let mut ids = Vec::new();
+        let mut start = 0;
+        for m in special_pattern.find_iter(text) {
+            ids.extend(self.encode(&text[start..m.start()]));
+            ids.push(self.tokenizer.special_tokens[m.as_str()]);
+            start = m.end();
+        }
+        ids.extend(self.encode(&text[start..]));
+        ids
+    }
+
+    /// Encodes `text` on up to `num_threads` threads and returns the same ids as `encode`.
+    ///
+    /// The text is cut into roughly equal segments at newlines that are followed by a
+    /// non-whitespace character. No match of the GPT-4 pattern can span such a position, so the
+    /// segments pre-tokenize exactly like the whole text; custom patterns need the same property.
+    #[cfg(feature = "std")]
+    pub fn encode_parallel(&self, text: &str, num_threads: usize) -> Vec<u32> {
+        let segments = split_at_line_boundaries(text, num_threads);
+        if segments.len() <= 1 {
+            return self.encode(text);
+        }
+        let encoded: Vec<Vec<u32>> = thread::scope(|scope| {
+            let handles: Vec<_> =
+                segments.iter().map(|segment| scope.spawn(|| self.encode(segment))).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        encoded.concat()
+    }
+
+    /// Reads the file at `path` and encodes it, using all available cores for large files split
+    /// with the GPT-4 pattern. Other patterns may match across the cuts `encode_parallel` makes,
+    /// so they are encoded on one thread.
+    #[cfg(feature = "std")]
+    pub fn encode_file(&self, path: &str) -> io::Result<Vec<u32>> {
+        const PARALLEL_THRESHOLD: usize = 1 << 20;
+
+        let text = fs::read_to_string(path)?;
+        if text.len() < PARALLEL_THRESHOLD || self.tokenizer.pattern != GPT4_SPLIT_PATTERN {
+            return Ok(self.encode(&text));
+        }
+        let num_threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Ok(self.encode_parallel(&text, num_threads))
+    }
+
+    /// Splits `text` into pieces of at most `max_tokens` tokens, returning the byte range and
+    /// token count of every piece. Consecutive pieces share up to `overlap` tokens.
+    ///
+    /// The text is pre-tokenized and encoded once, and pieces are packed greedily from whole
+    /// pre-tokens, so they never cut a word. A pre-token longer than the budget is cut between
+    /// its tokens, at a character boundary. Counts come from encoding the whole text, so without
+    /// overlap they add up to `encode(text).len()`; a piece encoded on its own may differ by a
+    /// token or two at its edges. A piece only exceeds the budget when a single character needs
+    /// more than `max_tokens` tokens.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `overlap` is not smaller than `max_tokens`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::tokenizers::regex::RegexTokenizer;
+    ///
+    /// let tokenizer = RegexTokenizer::new();
+    /// let text = "one two three four";
+    /// let pieces = tokenizer.split_to_token_budget(text, 8, 0);
+    /// let texts: Vec<&str> = pieces.iter().map(|(range, _)| &text[range.clone()]).collect();
+    /// assert_eq!(texts, ["one two", " three", " four"]);
+    /// assert_eq!(pieces[0].1, 7);
+    /// ```
+    pub fn split_to_token_budget(
+        &self,
+        text: &str,
+        max_tokens: usize,
+        overlap: usize,
+    ) -> Vec<(Range<usize>, usize)> {
+        assert!(overlap < max_tokens, "overlap must be smaller than max_tokens");
+
+        // The smallest units a piece is made of: whole pre-tokens, or parts of oversized ones.
+        let mut units: Vec<(Range<usize>, usize)> = Vec::new();
+        for m in self.compiled_pattern.find_iter(text) {
+            let ids = self.encode_chunk(m.as_str());
+            if ids.len() <= max_tokens {
+                units.push((m.range(), ids.len()));
+                continue;
+            }
+            let ends: Vec<usize> = ids
+                .iter()
+                .scan(m.start(), |offset, id| {
+                    *offset += self.tokenizer.vocab[id].len();
+                    Some(*offset)
+                })
+                .collect();
+            let mut first = 0;
+            while first < ids.len() {
+                let limit = (first + max_tokens).min(ids.len());
+                let at_boundary = |end: &usize| text.is_char_boundary(ends[end - 1]);
+                // The last token always ends on a boundary, so one of the searches succeeds.
+                let end = (first + 1..=limit)
+                    .rev()
+                    .find(at_boundary)
+                    .or_else(|| (limit + 1..=ids.len()).find(at_boundary))
+                    .unwrap();
+                let start = if first == 0 { m.start() } else { ends[first - 1] };
+                units.push((start..ends[end - 1], end - first));
+                first = end;
             }
         }
-        chunk_ids
+
+        let mut pieces = Vec::new();
+        let mut first = 0;
+        while first < units.len() {
+            let mut end = first;
+            let mut count = 0;
+            while end < units.len() && (end == first || count + units[end].1 <= max_tokens) {
+                count += units[end].1;
+                end += 1;
+            }
+            pieces.push((units[first].0.start..units[end - 1].0.end, count));
+            if end == units.len() {
+                break;
+            }
+            // Start the next piece with the trailing units of this one that fit the overlap, but
+            // always move forward by at least one unit.
+            let mut next = end;
+            let mut shared = 0;
+            while next > first + 1 && shared + units[next - 1].1 <= overlap {
+                shared += units[next - 1].1;
+                next -= 1;
+            }
+            first = next;
+        }
+        pieces
     }
-}
 
-impl TokenizerTrait for RegexTokenizer {
-    fn train(&mut self, text: &str, vocab_size: u32, verbose: bool) {
-        assert!(vocab_size >= 256);
-        let num_merges = vocab_size - 256;
-        let text_chunks: Vec<&str> =
-            self.compiled_pattern.find_iter(text).map(|m| m.as_str()).collect();
-        let mut ids: Vec<Vec<u32>> = text_chunks
+    /// Counts how often every pre-token chunk occurs in `text`.
+    pub fn chunk_counts(&self, text: &str) -> HashMap<String, u64> {
+        let mut counts = HashMap::default();
+        add_chunk_counts(&self.compiled_pattern, text, &mut counts);
+        counts
+    }
+
+    /// Trains on a table of pre-token chunks and how often they occur, as returned by
+    /// [`RegexTokenizer::chunk_counts`]. Merges never cross chunks, so this learns the same
+    /// merges as training on the text itself, while every distinct chunk is only processed once.
+    pub fn train_on_chunk_counts(&mut self, counts: &HashMap<String, u64>, options: &TrainOptions) {
+        assert!(options.vocab_size >= 256);
+        let num_merges = options.vocab_size - 256;
+        let mut chunks: Vec<(Vec<u32>, u64)> = counts
             .iter()
-            .map(|&chunk| chunk.as_bytes().iter().map(|&b| b as u32).collect())
+            .map(|(chunk, &count)| (chunk.bytes().map(u32::from).collect(), count))
             .collect();
+        chunks.sort_unstable();
+        let count_tokens = |chunks: &[(Vec<u32>, u64)]| -> usize {
+            chunks.iter().map(|(ids, count)| ids.len() * *count as usize).sum()
+        };
+        let mut stats = PairCounter::new();
+        let mut num_tokens = count_tokens(&chunks);
+        self.tokenizer.clear_merges();
         for i in 0..num_merges {
-            let mut stats = HashMap::new();
-            for chunk_ids in &ids {
-                let chunk = get_stats(chunk_ids);
-                for (pair, count) in chunk {
-                    *stats.entry(pair).or_insert(0) += count;
-                }
+            stats.clear();
+            for (chunk_ids, count) in &chunks {
+                stats.add_ids(chunk_ids, *count);
             }
-            if let Some(pair) = self.tokenizer.find_most_frequent_pair(&stats) {
-                let new_id = 256 + i as u32;
-                ids = ids.into_iter().map(|chunk_ids| merge(chunk_ids, pair, new_id)).collect();
-                self.tokenizer.merges.insert(pair, new_id);
-                let concatenated_parts = [
-                    self.tokenizer.vocab.get(&pair.0).unwrap_or(&vec![]).as_slice(),
-                    self.tokenizer.vocab.get(&pair.1).unwrap_or(&vec![]).as_slice(),
-                ]
-                .iter()
-                .flat_map(|&slice| slice.iter().copied())
-                .collect::<Vec<u8>>();
-                self.tokenizer.vocab.insert(new_id, concatenated_parts.clone());
+            let tokenizer = &self.tokenizer;
+            if let Some((pair, count)) =
+                stats.most_frequent_where(|pair| options.allows(&tokenizer.merged_token(pair)))
+            {
+                let new_id = 256 + i;
+                for (chunk_ids, _) in &mut chunks {
+                    *chunk_ids = merge(core::mem::take(chunk_ids), pair, new_id);
+                }
+                let tokens_before = num_tokens;
+                num_tokens = count_tokens(&chunks);
+                let step = MergeStep::new(new_id, pair, count, tokens_before, num_tokens);
+                if !options.keeps_gain(step.gain) {
+                    break;
+                }
+                if options.record_curve {
+                    self.tokenizer.growth_curve.push(step);
+                }
+                self.tokenizer.add_merge(pair, new_id);
 
-                if verbose {
+                #[cfg(feature = "std")]
+                if options.verbose {
                     println!(
                         "merge {}/{}: {:?} -> {} ({:?}) had {} occurrences",
                         i + 1,
-                        vocab_size - 256,
+                        num_merges,
                         pair,
                         new_id,
-                        String::from_utf8(concatenated_parts)
+                        String::from_utf8(self.tokenizer.vocab[&new_id].clone())
                             .unwrap_or_else(|_| "Invalid UTF-8".to_string()),
-                        stats[&pair],
+                        count,
                     );
                 }
             } else {
@@ -90,29 +325,142 @@ impl TokenizerTrait for RegexTokenizer {
         }
     }
 
-    fn encode(&self, text: &str) -> Vec<u32> {
-        let text_chunks: Vec<&str> =
-            self.compiled_pattern.find_iter(text).map(|m| m.as_str()).collect();
+    /// Trains on the concatenation of the files in `shards` without holding them in memory at
+    /// once.
+    ///
+    /// Worker threads, one per available core, each read shards and count their pre-token
+    /// chunks; the per-shard tables are then added up and the merges are learned once from the
+    /// aggregated table with [`RegexTokenizer::train_on_chunk_counts`]. Chunks never span two
+    /// shards, which only matters if a shard does not end at a line break.
+    #[cfg(feature = "std")]
+    pub fn train_sharded(
+        &mut self,
+        shards: Vec<PathBuf>,
+        options: &TrainOptions,
+    ) -> io::Result<()> {
+        let num_threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let num_threads = num_threads.min(shards.len()).max(1);
+        let next_shard = AtomicUsize::new(0);
+        let pattern = &self.compiled_pattern;
+        let tables: Vec<io::Result<HashMap<String, u64>>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..num_threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut counts = HashMap::new();
+                        while let Some(shard) =
+                            shards.get(next_shard.fetch_add(1, Ordering::Relaxed))
+                        {
+                            let text = fs::read_to_string(shard).map_err(|e| {
+                                io::Error::new(
+                                    e.kind(),
+                                    format!("Failed to read {}: {}", shard.display(), e),
+                                )
+                            })?;
+                            add_chunk_counts(pattern, &text, &mut counts);
+                        }
+                        Ok(counts)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for table in tables {
+            for (chunk, count) in table? {
+                *counts.entry(chunk).or_insert(0) += count;
+            }
+        }
+        self.train_on_chunk_counts(&counts, options);
+        Ok(())
+    }
+
+    /// Encodes a single pre-token chunk, applying merges in the order they were learned.
+    pub fn encode_chunk(&self, chunk: &str) -> Vec<u32> {
+        self.tokenizer.encode_bytes(chunk.as_bytes())
+    }
+}
 
-        let mut ids: Vec<u32> = Vec::new();
+/// Adds one to the count of every chunk of `text`, only allocating for chunks seen for the
+/// first time.
+fn add_chunk_counts(pattern: &Regex, text: &str, counts: &mut HashMap<String, u64>) {
+    for m in pattern.find_iter(text) {
+        match counts.get_mut(m.as_str()) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(m.as_str().to_string(), 1);
+            }
+        }
+    }
+}
 
-        for chunk in &text_chunks {
-            let chunk_ids = self.encode_chunk(chunk);
-            ids.extend(chunk_ids);
+/// Splits `text` into at most `max_segments` pieces of similar size. Every cut is placed right
+/// after a newline that is followed by a non-whitespace character.
+#[cfg(feature = "std")]
+fn split_at_line_boundaries(text: &str, max_segments: usize) -> Vec<&str> {
+    let bytes = text.as_bytes();
+    let target_len = text.len() / max_segments.max(1);
+    let mut segments = Vec::with_capacity(max_segments);
+    let mut start = 0;
+    while segments.len() + 1 < max_segments {
+        let boundary = (start + target_len.max(1)..bytes.len())
+            .find(|&i| bytes[i - 1] == b'\n' && !text[i..].starts_with(char::is_whitespace));
+        match boundary {
+            Some(boundary) => {
+                segments.push(&text[start..boundary]);
+                start = boundary;
+            }
+            None => break,
+        }
+    }
+    segments.push(&text[start..]);
+    segments
+}
+
+impl AsRef<Tokenizer> for RegexTokenizer {
+    fn as_ref(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+}
+
+impl Default for RegexTokenizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenizerTrait for RegexTokenizer {
+    fn train_with_options(&mut self, text: &str, options: &TrainOptions) {
+        let counts = self.chunk_counts(text);
+        self.train_on_chunk_counts(&counts, options);
+    }
+
+    fn encode_into(&self, text: &str, scratch: &mut EncodeScratch, out: &mut Vec<u32>) {
+        for m in self.compiled_pattern.find_iter(text) {
+            self.tokenizer.encode_bytes_into(m.as_str().as_bytes(), &mut scratch.ids, out);
         }
-        ids
     }
 
     fn decode(&self, ids: &[u32]) -> String {
         self.tokenizer.decode(ids)
     }
 
-    fn save(&self, file_prefix: &str) -> std::io::Result<()> {
-        self.tokenizer.save(file_prefix)
+    fn load_bytes(&mut self, model: &[u8]) -> Result<(), ModelError> {
+        self.tokenizer.load_bytes(model)?;
+        if self.tokenizer.pattern.is_empty() {
+            return Err(ModelError::new(
+                "Model has no split pattern, load it with the basic tokenizer instead",
+            ));
+        }
+        self.compiled_pattern = compile_model_pattern(&self.tokenizer.pattern)
+            .map_err(|e| ModelError::new(alloc::format!("Invalid split pattern: {}", e)))?;
+        self.special_pattern = special_pattern(&self.tokenizer.special_tokens);
+        Ok(())
     }
 
-    fn load(&mut self, model_file: &str) -> std::io::Result<()> {
-        self.tokenizer.load(model_file)
+    #[cfg(feature = "std")]
+    fn save(&self, file_prefix: &str) -> io::Result<()> {
+        self.tokenizer.save(file_prefix)
     }
 }
 
@@ -130,4 +478,224 @@ mod tests {
             assert_eq!(test_string, decoded);
         }
     }
+
+    #[test]
+    fn test_encode_into_reuses_scratch() {
+        let mut tokenizer = RegexTokenizer::new();
+        let text = fs::read_to_string("data/taylorswift.txt").unwrap();
+        tokenizer.train(&text[..10_000], 300, false);
+
+        let mut scratch = EncodeScratch::new();
+        let mut out = vec![7];
+        tokenizer.encode_into("hello world", &mut scratch, &mut out);
+        assert_eq!(out[0], 7);
+        assert_eq!(out[1..], tokenizer.encode("hello world"));
+
+        for line in text.lines().take(200) {
+            out.clear();
+            tokenizer.encode_into(line, &mut scratch, &mut out);
+            assert_eq!(out, tokenizer.encode(line));
+        }
+    }
+
+    #[test]
+    fn test_encode_chunk_uses_merge_order() {
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.tokenizer.add_merge((b'b' as u32, b'c' as u32), 256);
+        tokenizer.tokenizer.add_merge((b'a' as u32, b'b' as u32), 257);
+        // A left-to-right scan would merge "ab" first, but "bc" was learned earlier.
+        assert_eq!(tokenizer.encode_chunk("abc"), vec![b'a' as u32, 256]);
+    }
+
+    #[test]
+    fn test_train_with_blocklist() {
+        use crate::train::Blocked;
+
+        let text = "password password password pass word";
+        let options = TrainOptions {
+            blocklist: vec![Blocked::Bytes(b"word".to_vec())],
+            ..TrainOptions::new(280)
+        };
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.train_with_options(text, &options);
+        assert!(tokenizer.tokenizer.vocab.values().any(|token| token == b" pass"));
+        assert!(tokenizer.tokenizer.find_tokens_containing(b"word").is_empty());
+        assert_eq!(tokenizer.decode(&tokenizer.encode(text)), text);
+    }
+
+    #[test]
+    fn test_training_test_vector() {
+        // Exact tokens expected on every platform; the count-1 pairs at the end are taken in
+        // ascending pair order.
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.train("the cat and the hat, then the bath", 264, false);
+        let learned: Vec<&[u8]> =
+            (256..264).map(|idx| tokenizer.tokenizer.vocab[&idx].as_slice()).collect();
+        let expected: [&[u8]; 8] = [b"th", b"the", b" the", b"at", b" a", b" b", b" c", b" h"];
+        assert_eq!(learned, expected);
+        assert_eq!(tokenizer.tokenizer.merges[&(32, 257)], 258);
+    }
+
+    #[test]
+    fn test_retrain_starts_from_scratch() {
+        let text = "the cat and the hat, then the bath";
+        let mut once = RegexTokenizer::new();
+        once.train(text, 264, false);
+        let mut twice = RegexTokenizer::new();
+        twice.train("hello hello world", 270, false);
+        twice.train(text, 264, false);
+        assert!(twice.tokenizer.merges_in_order().eq(once.tokenizer.merges_in_order()));
+        assert_eq!(twice.tokenizer.vocab, once.tokenizer.vocab);
+    }
+
+    #[test]
+    fn test_train_sharded() -> io::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let text = fs::read_to_string("data/taylorswift.txt")?;
+        let text = &text[..30_000];
+        let mut shards = Vec::new();
+        for (i, segment) in split_at_line_boundaries(text, 4).iter().enumerate() {
+            let path = temp_dir.path().join(format!("shard-{}.txt", i));
+            fs::write(&path, segment)?;
+            shards.push(path);
+        }
+        assert_eq!(shards.len(), 4);
+
+        let options = TrainOptions { record_curve: true, ..TrainOptions::new(320) };
+        let mut sharded = RegexTokenizer::new();
+        sharded.train_sharded(shards.clone(), &options)?;
+        let mut whole = RegexTokenizer::new();
+        whole.train_with_options(text, &options);
+        assert_eq!(sharded.tokenizer.merges, whole.tokenizer.merges);
+        assert_eq!(sharded.tokenizer.growth_curve, whole.tokenizer.growth_curve);
+
+        shards.push(temp_dir.path().join("missing.txt"));
+        assert!(RegexTokenizer::new().train_sharded(shards, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_at_line_boundaries() {
+        let text = "one\ntwo\n\n  three\nfour\n";
+        let segments = split_at_line_boundaries(text, 3);
+        assert_eq!(segments.concat(), text);
+        assert_eq!(segments, vec!["one\ntwo\n\n  three\n", "four\n"]);
+        assert_eq!(split_at_line_boundaries("no newlines", 4), vec!["no newlines"]);
+        assert_eq!(split_at_line_boundaries("", 4), vec![""]);
+    }
+
+    #[test]
+    fn test_encode_parallel() {
+        let text = std::fs::read_to_string("data/taylorswift.txt").unwrap();
+        let text = &text[..text.len().min(20_000)];
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.train(text, 300, false);
+        let expected = tokenizer.encode(text);
+        for num_threads in [1, 2, 7] {
+            assert_eq!(tokenizer.encode_parallel(text, num_threads), expected);
+        }
+    }
+
+    #[test]
+    fn test_encode_file_custom_pattern() -> io::Result<()> {
+        // Every match of this pattern spans whole lines, which `encode_parallel` would cut.
+        let mut tokenizer = RegexTokenizer::with_pattern(r"[^;]+|;").unwrap();
+        tokenizer.train("x\nx\nx\nx\n", 258, false);
+        let text = "x\n".repeat(600_003);
+        assert_ne!(tokenizer.encode_parallel(&text, 4), tokenizer.encode(&text));
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("large.txt");
+        fs::write(&path, &text)?;
+        assert_eq!(tokenizer.encode_file(path.to_str().unwrap())?, tokenizer.encode(&text));
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_pattern() {
+        let mut tokenizer = RegexTokenizer::with_pattern(r"\w+|\s+|[^\w\s]+").unwrap();
+        tokenizer.train("aaa aaa aaa", 257, false);
+        // "aaa" pre-tokenizes into its own chunk, so "a " is never a candidate.
+        assert_eq!(tokenizer.tokenizer.merges.get(&(97, 97)), Some(&256));
+        assert!(RegexTokenizer::with_pattern("(").is_err());
+    }
+
+    #[test]
+    fn test_encode_with_special() {
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.register_special_tokens(HashMap::from([("<|endoftext|>".to_string(), 256)]));
+        let ids = tokenizer.encode_with_special("hi<|endoftext|>yo");
+        assert_eq!(ids, vec![104, 105, 256, 121, 111]);
+        assert_eq!(tokenizer.decode(&ids), "hi<|endoftext|>yo");
+        assert_eq!(tokenizer.encode("<|endoftext|>").len(), "<|endoftext|>".len());
+
+        // Loaded special tokens are picked up too, and the longest one wins.
+        let model = format!("{}\n2\n<|end|> 256\n<|endoftext|> 257\n", GPT4_SPLIT_PATTERN);
+        tokenizer.load_bytes(model.as_bytes()).unwrap();
+        assert_eq!(tokenizer.encode_with_special("<|endoftext|><|end|>"), vec![257, 256]);
+    }
+
+    #[test]
+    fn test_split_to_token_budget() {
+        let mut tokenizer = RegexTokenizer::new();
+        let text = fs::read_to_string("data/taylorswift.txt").unwrap();
+        let text = &text[..20_000];
+        tokenizer.train(text, 300, false);
+
+        let pieces = tokenizer.split_to_token_budget(text, 100, 0);
+        assert_eq!(
+            pieces.iter().map(|(_, count)| count).sum::<usize>(),
+            tokenizer.encode(text).len()
+        );
+        assert_eq!(pieces.first().unwrap().0.start, 0);
+        assert_eq!(pieces.last().unwrap().0.end, text.len());
+        for window in pieces.windows(2) {
+            assert_eq!(window[0].0.end, window[1].0.start);
+        }
+        for (range, count) in &pieces {
+            assert!(*count <= 100);
+            assert!(tokenizer.encode(&text[range.clone()]).len().abs_diff(*count) <= 2);
+        }
+
+        let overlapping = tokenizer.split_to_token_budget(text, 100, 20);
+        assert!(overlapping.len() > pieces.len());
+        for window in overlapping.windows(2) {
+            assert!(window[1].0.start < window[0].0.end);
+            assert!(window[0].0.start < window[1].0.start);
+        }
+        assert!(overlapping.iter().all(|(_, count)| *count <= 100));
+        assert_eq!(overlapping.last().unwrap().0.end, text.len());
+    }
+
+    #[test]
+    fn test_split_to_token_budget_cuts_long_pre_tokens() {
+        let tokenizer = RegexTokenizer::new();
+        let text = "a".repeat(20);
+        let expected: Vec<(Range<usize>, usize)> = vec![(0..8, 8), (8..16, 8), (16..20, 4)];
+        assert_eq!(tokenizer.split_to_token_budget(&text, 8, 0), expected);
+
+        // Never cut inside a character, even if that means going over the budget.
+        assert_eq!(tokenizer.split_to_token_budget("éé", 3, 0), vec![(0..2, 2), (2..4, 2)]);
+        assert_eq!(tokenizer.split_to_token_budget("😉", 2, 0), vec![(0..4, 4)]);
+        assert!(tokenizer.split_to_token_budget("", 8, 0).is_empty());
+    }
+
+    #[test]
+    fn test_pattern_cache() {
+        let pattern = r"\p{L}+|\s+|[^\p{L}\s]+";
+        let tokenizer = RegexTokenizer::with_pattern(pattern).unwrap();
+        assert!(PATTERN_CACHE.lock().unwrap().contains_key(pattern));
+        let other = RegexTokenizer::with_pattern(pattern).unwrap();
+        assert_eq!(other.compiled_pattern.as_str(), pattern);
+        assert_eq!(tokenizer.encode("héllo, wörld"), other.encode("héllo, wörld"));
+
+        assert!(RegexTokenizer::with_pattern("(unclosed").is_err());
+        assert!(!PATTERN_CACHE.lock().unwrap().contains_key("(unclosed"));
+
+        // Patterns of loaded models stay out of the cache.
+        let loaded_pattern = r"\p{L}+|\p{N}+|[^\p{L}\p{N}]+";
+        let mut loaded = RegexTokenizer::new();
+        loaded.load_bytes(format!("{}\n0\n", loaded_pattern).as_bytes()).unwrap();
+        assert_eq!(loaded.compiled_pattern.as_str(), loaded_pattern);
+        assert!(!PATTERN_CACHE.lock().unwrap().contains_key(loaded_pattern));
+    }
 }
@@ -0,0 +1,405 @@
+//! Import of SentencePiece `.model` files.
+//!
+//! SentencePiece models are serialized `ModelProto` protobuf messages. Only the fields needed to
+//! rebuild a byte-level vocabulary are decoded: the pieces with their scores and types, the model
+//! type from the trainer spec and `add_dummy_prefix` from the normalizer spec.
+//!
+//! The converted [`Tokenizer`] works on bytes, so every piece is turned into its UTF-8 bytes with
+//! the `▁` space marker replaced by a plain space, and merges are created in piece priority order
+//! (piece order for BPE models, descending score for unigram models). Encoding with the result
+//! matches SentencePiece with the following caveats:
+//!
+//! * Ids are renumbered: bytes keep ids 0..256 and merged tokens follow. The loaders return a
+//!   table from SentencePiece ids to token ids to translate between the two.
+//! * Pieces whose bytes cannot be built from earlier pieces (e.g. single multi-byte characters)
+//!   get intermediate byte-level tokens that do not exist in the SentencePiece vocab.
+//! * Normalization rules (such as `nmt_nfkc`) are not applied and the dummy prefix is not added;
+//!   prepend a space yourself when [`SentencePieceModel::add_dummy_prefix`] is set.
+//! * Unigram models are segmented greedily by merge priority instead of with Viterbi decoding,
+//!   so segmentations can differ.
+//! * Characters missing from the vocab are encoded as raw bytes instead of `<unk>`.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use crate::tokenizers::basic::Tokenizer;
+use crate::TokenizerTrait;
+
+/// The algorithm a SentencePiece model was trained with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModelType {
+    Unigram,
+    Bpe,
+    Word,
+    Char,
+}
+
+/// The role of a piece in a SentencePiece vocab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PieceType {
+    Normal,
+    Unknown,
+    Control,
+    UserDefined,
+    Unused,
+    Byte,
+}
+
+/// A single entry of a SentencePiece vocab.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Piece {
+    pub piece: String,
+    pub score: f32,
+    pub kind: PieceType,
+}
+
+/// The parts of a SentencePiece `ModelProto` needed for conversion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentencePieceModel {
+    pub pieces: Vec<Piece>,
+    pub model_type: ModelType,
+    pub add_dummy_prefix: bool,
+}
+
+impl SentencePieceModel {
+    /// Reads and parses the `.model` file at `path`.
+    pub fn from_file(path: &str) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Parses a serialized `ModelProto`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut pieces = Vec::new();
+        let mut model_type = ModelType::Unigram;
+        let mut add_dummy_prefix = true;
+
+        let mut reader = ProtoReader::new(bytes);
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, WireValue::Bytes(piece)) => pieces.push(parse_piece(piece)?),
+                (2, WireValue::Bytes(trainer_spec)) => {
+                    let mut reader = ProtoReader::new(trainer_spec);
+                    while let Some((field, value)) = reader.next_field()? {
+                        if let (3, WireValue::Varint(kind)) = (field, value) {
+                            model_type = match kind {
+                                1 => ModelType::Unigram,
+                                2 => ModelType::Bpe,
+                                3 => ModelType::Word,
+                                4 => ModelType::Char,
+                                _ => return Err(invalid(format!("Unknown model type {}", kind))),
+                            };
+                        }
+                    }
+                }
+                (3, WireValue::Bytes(normalizer_spec)) => {
+                    let mut reader = ProtoReader::new(normalizer_spec);
+                    while let Some((field, value)) = reader.next_field()? {
+                        if let (3, WireValue::Varint(flag)) = (field, value) {
+                            add_dummy_prefix = flag != 0;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if pieces.is_empty() {
+            return Err(invalid("SentencePiece model has no pieces"));
+        }
+        Ok(SentencePieceModel { pieces, model_type, add_dummy_prefix })
+    }
+
+    /// Converts the vocab into a byte-level [`Tokenizer`].
+    ///
+    /// Returns the tokenizer together with a table mapping every SentencePiece id to the id of
+    /// the same piece in the tokenizer (`None` for unused pieces). Control and unknown pieces
+    /// become special tokens.
+    pub fn to_tokenizer(&self) -> (Tokenizer, Vec<Option<u32>>) {
+        let mut tokenizer = Tokenizer::new();
+        let mut piece_ids = vec![None; self.pieces.len()];
+
+        let mut order: Vec<usize> = (0..self.pieces.len()).collect();
+        if self.model_type != ModelType::Bpe {
+            // Stable sort keeps the piece order for equal scores.
+            order.sort_by(|&a, &b| self.pieces[b].score.total_cmp(&self.pieces[a].score));
+        }
+
+        let mut next_idx = 256;
+        let mut special_pieces = Vec::new();
+        for sp_id in order {
+            let piece = &self.pieces[sp_id];
+            match piece.kind {
+                PieceType::Normal | PieceType::UserDefined => {
+                    let text = piece.piece.replace('\u{2581}', " ");
+                    let mut ids = tokenizer.encode(&text);
+                    while ids.len() > 1 {
+                        let pair = (ids[0], ids[1]);
+                        tokenizer.add_merge(pair, next_idx);
+                        ids.splice(0..2, [next_idx]);
+                        next_idx += 1;
+                    }
+                    piece_ids[sp_id] = ids.first().copied();
+                }
+                PieceType::Byte => piece_ids[sp_id] = parse_byte_piece(&piece.piece),
+                PieceType::Control | PieceType::Unknown => special_pieces.push(sp_id),
+                PieceType::Unused => {}
+            }
+        }
+
+        let mut special_tokens = HashMap::new();
+        for sp_id in special_pieces {
+            special_tokens.insert(self.pieces[sp_id].piece.clone(), next_idx);
+            piece_ids[sp_id] = Some(next_idx);
+            next_idx += 1;
+        }
+        tokenizer.register_special_tokens(special_tokens);
+
+        (tokenizer, piece_ids)
+    }
+}
+
+impl Tokenizer {
+    /// Imports a SentencePiece `.model` file (BPE or unigram) as a byte-level tokenizer.
+    ///
+    /// Like [`SentencePieceModel::to_tokenizer`], returns the table mapping every SentencePiece
+    /// id to its token id, since the tokens are renumbered. See the
+    /// [module documentation](self) for how the result differs from SentencePiece.
+    pub fn from_sentencepiece(path: &str) -> io::Result<(Tokenizer, Vec<Option<u32>>)> {
+        let model = SentencePieceModel::from_file(path)?;
+        match model.model_type {
+            ModelType::Bpe | ModelType::Unigram => Ok(model.to_tokenizer()),
+            other => Err(invalid(format!("Unsupported SentencePiece model type {:?}", other))),
+        }
+    }
+}
+
+/// Parses byte fallback pieces of the form `<0xAB>`.
+fn parse_byte_piece(piece: &str) -> Option<u32> {
+    let hex = piece.strip_prefix("<0x")?.strip_suffix('>')?;
+    u8::from_str_radix(hex, 16).ok().map(u32::from)
+}
+
+fn parse_piece(bytes: &[u8]) -> io::Result<Piece> {
+    let mut piece = Piece { piece: String::new(), score: 0.0, kind: PieceType::Normal };
+    let mut reader = ProtoReader::new(bytes);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, WireValue::Bytes(text)) => {
+                piece.piece = String::from_utf8(text.to_vec())
+                    .map_err(|_| invalid("SentencePiece piece is not valid UTF-8"))?;
+            }
+            (2, WireValue::Fixed32(bits)) => piece.score = f32::from_bits(bits),
+            (3, WireValue::Varint(kind)) => {
+                piece.kind = match kind {
+                    1 => PieceType::Normal,
+                    2 => PieceType::Unknown,
+                    3 => PieceType::Control,
+                    4 => PieceType::UserDefined,
+                    5 => PieceType::Unused,
+                    6 => PieceType::Byte,
+                    _ => return Err(invalid(format!("Unknown piece type {}", kind))),
+                };
+            }
+            _ => {}
+        }
+    }
+    Ok(piece)
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64,
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// A minimal reader for the protobuf wire format.
+struct ProtoReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        ProtoReader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| invalid("Truncated SentencePiece model"))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid("Invalid varint in SentencePiece model"))
+    }
+
+    fn next_field(&mut self) -> io::Result<Option<(u64, WireValue<'a>)>> {
+        if self.pos == self.bytes.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => WireValue::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                WireValue::Fixed64
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                WireValue::Bytes(self.take(len)?)
+            }
+            5 => {
+                let bytes = self.take(4)?;
+                WireValue::Fixed32(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            wire_type => {
+                return Err(invalid(format!("Unsupported protobuf wire type {}", wire_type)))
+            }
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn varint(mut value: u64, out: &mut Vec<u8>) {
+        while value >= 0x80 {
+            out.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn bytes_field(field: u64, bytes: &[u8], out: &mut Vec<u8>) {
+        varint(field << 3 | 2, out);
+        varint(bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn varint_field(field: u64, value: u64, out: &mut Vec<u8>) {
+        varint(field << 3, out);
+        varint(value, out);
+    }
+
+    fn model_proto(model_type: u64, pieces: &[(&str, f32, u64)]) -> Vec<u8> {
+        let mut proto = Vec::new();
+        for &(piece, score, kind) in pieces {
+            let mut message = Vec::new();
+            bytes_field(1, piece.as_bytes(), &mut message);
+            varint(2 << 3 | 5, &mut message);
+            message.extend_from_slice(&score.to_le_bytes());
+            varint_field(3, kind, &mut message);
+            bytes_field(1, &message, &mut proto);
+        }
+        let mut trainer_spec = Vec::new();
+        bytes_field(1, b"corpus.txt", &mut trainer_spec);
+        varint_field(3, model_type, &mut trainer_spec);
+        bytes_field(2, &trainer_spec, &mut proto);
+        let mut normalizer_spec = Vec::new();
+        varint_field(3, 0, &mut normalizer_spec);
+        bytes_field(3, &normalizer_spec, &mut proto);
+        proto
+    }
+
+    const BPE_PIECES: [(&str, f32, u64); 10] = [
+        ("<unk>", 0.0, 2),
+        ("<s>", 0.0, 3),
+        ("</s>", 0.0, 3),
+        ("<0x41>", 0.0, 6),
+        ("▁t", -0.0, 1),
+        ("he", -1.0, 1),
+        ("▁the", -2.0, 1),
+        ("▁", -3.0, 1),
+        ("t", -4.0, 1),
+        ("é", -5.0, 1),
+    ];
+
+    #[test]
+    fn test_parse_model() {
+        let model = SentencePieceModel::from_bytes(&model_proto(2, &BPE_PIECES)).unwrap();
+        assert_eq!(model.model_type, ModelType::Bpe);
+        assert!(!model.add_dummy_prefix);
+        assert_eq!(model.pieces.len(), BPE_PIECES.len());
+        assert_eq!(
+            model.pieces[5],
+            Piece { piece: "he".into(), score: -1.0, kind: PieceType::Normal }
+        );
+        assert_eq!(model.pieces[1].kind, PieceType::Control);
+    }
+
+    #[test]
+    fn test_bpe_conversion() {
+        let model = SentencePieceModel::from_bytes(&model_proto(2, &BPE_PIECES)).unwrap();
+        let (tokenizer, piece_ids) = model.to_tokenizer();
+
+        assert_eq!(piece_ids[3], Some(0x41));
+        assert_eq!(piece_ids[7], Some(b' ' as u32));
+        assert_eq!(piece_ids[4], Some(256));
+        assert_eq!(piece_ids[5], Some(257));
+        assert_eq!(piece_ids[6], Some(258));
+        assert_eq!(tokenizer.vocab[&258], b" the");
+
+        let to_sp: HashMap<u32, usize> = piece_ids
+            .iter()
+            .enumerate()
+            .filter_map(|(sp_id, &id)| id.map(|id| (id, sp_id)))
+            .collect();
+        let sp_ids = |text: &str| -> Vec<usize> {
+            tokenizer.encode(text).iter().map(|id| to_sp[id]).collect()
+        };
+        assert_eq!(sp_ids(" the"), vec![6]);
+        assert_eq!(sp_ids(" he"), vec![7, 5]);
+        assert_eq!(sp_ids(" tt"), vec![4, 8]);
+        assert_eq!(sp_ids("é"), vec![9]);
+
+        assert_eq!(tokenizer.special_tokens.len(), 3);
+        assert_eq!(tokenizer.decode(&[piece_ids[1].unwrap(), 258]), "<s> the");
+    }
+
+    #[test]
+    fn test_unigram_orders_by_score() {
+        let pieces = [("ab", -5.0, 1), ("bc", -1.0, 1), ("a", -2.0, 1)];
+        let model = SentencePieceModel::from_bytes(&model_proto(1, &pieces)).unwrap();
+        assert_eq!(model.model_type, ModelType::Unigram);
+        let (tokenizer, piece_ids) = model.to_tokenizer();
+        // "bc" has the best score, so it is merged first and wins the overlap.
+        assert_eq!(piece_ids[1], Some(256));
+        assert_eq!(piece_ids[0], Some(257));
+        assert_eq!(tokenizer.encode("abc"), vec![b'a' as u32, 256]);
+    }
+
+    #[test]
+    fn test_from_sentencepiece() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("test.model");
+        fs::write(&path, model_proto(2, &BPE_PIECES))?;
+        let (tokenizer, piece_ids) = Tokenizer::from_sentencepiece(path.to_str().unwrap())?;
+        assert_eq!(tokenizer.decode(&tokenizer.encode(" the tea")), " the tea");
+        let the = piece_ids.iter().position(|&id| id == Some(tokenizer.encode(" the")[0]));
+        assert_eq!(the, Some(6));
+
+        fs::write(&path, model_proto(3, &BPE_PIECES))?;
+        assert!(Tokenizer::from_sentencepiece(path.to_str().unwrap()).is_err());
+        fs::write(&path, [0x0a, 0x05, 0x01])?;
+        assert!(Tokenizer::from_sentencepiece(path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+}
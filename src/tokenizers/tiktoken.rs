@@ -0,0 +1,279 @@
+//! Interoperability with tiktoken's `.tiktoken` rank files.
+//!
+//! A rank file has one line per token: the base64 encoded token bytes followed by its rank.
+//! tiktoken merges pairs in rank order, which is the same as this crate's merge order, so token
+//! ids are used as ranks directly. The split pattern is not part of the format and is written to
+//! a `<path>.pattern` file next to the ranks.
+//!
+//! Importing goes the other way: the merge that created every multi-byte token is recovered by
+//! running BPE on its bytes with the lower ranks only, which leaves exactly two parts. Ids are
+//! renumbered so that bytes keep ids 0..256 and merged tokens follow in rank order, so a chunk
+//! is merged exactly like tiktoken merges it. The ids only match tiktoken's ranks for files whose
+//! first 256 ranks are the bytes in order (such as files written by
+//! [`Tokenizer::export_tiktoken`]). Special tokens are not part of the format and have to be
+//! registered separately.
+//!
+//! Token counts are an approximation of tiktoken's: the split patterns of OpenAI's encodings
+//! use a lookahead (`\s+(?!\S)`) and possessive quantifiers, which the `regex` crate does not
+//! support. [`GPT4_SPLIT_PATTERN`] leaves them out, so a run of several spaces before a word is
+//! split differently (tiktoken attaches the last space to the word), which can change counts
+//! for text with indentation or aligned columns.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+
+use crate::tokenizers::basic::Tokenizer;
+use crate::tokenizers::regex::{RegexTokenizer, GPT4_SPLIT_PATTERN};
+use crate::util::{base64_decode, base64_encode};
+
+impl Tokenizer {
+    /// Writes the vocab as a tiktoken rank file at `path` and the split pattern, if any, to
+    /// `<path>.pattern`.
+    ///
+    /// tiktoken requires every token to be unique, so this fails without writing anything when
+    /// BPE learned the same bytes twice (e.g. via `a + bc` and `ab + c`): the rank file could not
+    /// hold the second id, which this vocab can still produce. Special tokens are not part of the
+    /// rank file and have to be passed to tiktoken separately.
+    pub fn export_tiktoken(&self, path: &str) -> io::Result<()> {
+        let mut ids: Vec<u32> = self.vocab.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut first_ids = HashMap::new();
+        for &id in &ids {
+            if let Some(first) = first_ids.insert(self.vocab[&id].as_slice(), id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Tokens {} and {} have the same bytes, which tiktoken does not support",
+                        first, id
+                    ),
+                ));
+            }
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        for id in ids {
+            writeln!(file, "{} {}", base64_encode(&self.vocab[&id]), id)?;
+        }
+        file.flush()?;
+
+        if !self.pattern.is_empty() {
+            fs::write(format!("{}.pattern", path), &self.pattern)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tokenizer {
+    /// Imports a tiktoken rank file, reading the split pattern from `<path>.pattern` if it
+    /// exists. A trailing line break in that file is ignored, so it can be edited by hand. See
+    /// the [module documentation](self) for how ids are assigned.
+    pub fn from_tiktoken(path: &str) -> io::Result<Tokenizer> {
+        let mut tokenizer = Tokenizer::from_tiktoken_ranks(&fs::read_to_string(path)?)?;
+        match fs::read_to_string(format!("{}.pattern", path)) {
+            Ok(pattern) => tokenizer.pattern = pattern.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(tokenizer)
+    }
+
+    /// Builds a tokenizer from the content of a tiktoken rank file.
+    pub fn from_tiktoken_ranks(content: &str) -> io::Result<Tokenizer> {
+        let mut ranks: HashMap<Vec<u8>, u32> = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(token, rank)| {
+                Some((base64_decode(token)?, rank.trim().parse::<u32>().ok()?))
+            });
+            let (token, rank) = parsed.ok_or_else(|| {
+                invalid(format!("line {}: expected a base64 token and a rank", i + 1))
+            })?;
+            if token.is_empty() || ranks.insert(token, rank).is_some() {
+                return Err(invalid(format!("line {}: empty or duplicate token", i + 1)));
+            }
+        }
+        let mut ordered: Vec<(&[u8], u32)> =
+            ranks.iter().map(|(token, &rank)| (token.as_slice(), rank)).collect();
+        ordered.sort_unstable_by_key(|&(_, rank)| rank);
+
+        let mut tokenizer = Tokenizer::new();
+        let mut ids: HashMap<&[u8], u32> = HashMap::new();
+        let mut next_idx = 256;
+        for (token, rank) in ordered {
+            if let [byte] = token {
+                ids.insert(token, *byte as u32);
+                continue;
+            }
+            let parts = bpe_below_rank(token, rank, &ranks);
+            let pair = match parts.as_slice() {
+                [first, second] => {
+                    ids.get(&token[first.clone()]).zip(ids.get(&token[second.clone()]))
+                }
+                _ => None,
+            };
+            let Some((&first, &second)) = pair else {
+                return Err(invalid(format!(
+                    "rank {} cannot be built by merging two lower ranks",
+                    rank
+                )));
+            };
+            let pair = (first, second);
+            tokenizer.add_merge(pair, next_idx);
+            ids.insert(token, next_idx);
+            next_idx += 1;
+        }
+        Ok(tokenizer)
+    }
+}
+
+/// Runs BPE on `token` using only the ranks below `max_rank`, returning the byte ranges of the
+/// resulting parts.
+fn bpe_below_rank(token: &[u8], max_rank: u32, ranks: &HashMap<Vec<u8>, u32>) -> Vec<Range<usize>> {
+    let mut parts: Vec<Range<usize>> = (0..token.len()).map(|i| i..i + 1).collect();
+    loop {
+        let best = parts
+            .windows(2)
+            .enumerate()
+            .filter_map(|(i, pair)| {
+                let rank = *ranks.get(&token[pair[0].start..pair[1].end])?;
+                (rank < max_rank).then_some((rank, i))
+            })
+            .min();
+        match best {
+            Some((_, i)) => {
+                parts[i].end = parts[i + 1].end;
+                parts.remove(i + 1);
+            }
+            None => return parts,
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl RegexTokenizer {
+    /// Imports a tiktoken rank file, see [`Tokenizer::from_tiktoken`]. Without a
+    /// `<path>.pattern` file the GPT-4 split pattern is used, which approximates the pattern of
+    /// `cl100k_base` without its whitespace lookahead (see the [module documentation](self)).
+    pub fn from_tiktoken(path: &str) -> io::Result<RegexTokenizer> {
+        let mut tokenizer = Tokenizer::from_tiktoken(path)?;
+        if tokenizer.pattern.is_empty() {
+            tokenizer.pattern = GPT4_SPLIT_PATTERN.to_string();
+        }
+        RegexTokenizer::from_tokenizer(tokenizer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the vocab and split pattern in tiktoken format, see [`Tokenizer::export_tiktoken`].
+    pub fn export_tiktoken(&self, path: &str) -> io::Result<()> {
+        self.tokenizer.export_tiktoken(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizers::regex::GPT4_SPLIT_PATTERN;
+    use crate::TokenizerTrait;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_export_tiktoken() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("test.tiktoken");
+        let path = path.to_str().unwrap();
+
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.train("hello hello hello", 258, false);
+        tokenizer.export_tiktoken(path)?;
+
+        let ranks = fs::read_to_string(path)?;
+        let lines: Vec<&str> = ranks.lines().collect();
+        assert_eq!(lines.len(), 258);
+        assert_eq!(lines[0], "AA== 0");
+        assert_eq!(lines[b'h' as usize], "aA== 104");
+        assert_eq!(lines[257], format!("{} 257", base64_encode(&tokenizer.tokenizer.vocab[&257])));
+        assert_eq!(fs::read_to_string(format!("{}.pattern", path))?, GPT4_SPLIT_PATTERN);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_tiktoken_rejects_duplicates() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("test.tiktoken");
+        let path = path.to_str().unwrap();
+
+        let mut tokenizer = Tokenizer::new();
+        // "abc" is built twice, as 258 and 259.
+        for (pair, idx) in [((97, 98), 256), ((98, 99), 257), ((256, 99), 258), ((97, 257), 259)] {
+            tokenizer.add_merge(pair, idx);
+        }
+        let error = tokenizer.export_tiktoken(path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().starts_with("Tokens 258 and 259"));
+        assert!(!std::path::Path::new(path).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiktoken_roundtrip() -> io::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("test.tiktoken");
+        let path = path.to_str().unwrap();
+
+        let text = fs::read_to_string("data/taylorswift.txt")?;
+        let mut tokenizer = RegexTokenizer::new();
+        tokenizer.train(&text[..10_000], 320, false);
+        tokenizer.export_tiktoken(path)?;
+
+        let imported = RegexTokenizer::from_tiktoken(path)?;
+        assert_eq!(imported.tokenizer.pattern, GPT4_SPLIT_PATTERN);
+        assert_eq!(imported.tokenizer.vocab, tokenizer.tokenizer.vocab);
+        assert_eq!(imported.encode(&text[10_000..20_000]), tokenizer.encode(&text[10_000..20_000]));
+
+        // Editors add a final newline when saving the pattern file.
+        fs::write(format!("{}.pattern", path), format!("{}\r\n", GPT4_SPLIT_PATTERN))?;
+        let imported = RegexTokenizer::from_tiktoken(path)?;
+        assert_eq!(imported.tokenizer.pattern, GPT4_SPLIT_PATTERN);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_tiktoken_ranks_renumbers_bytes() -> io::Result<()> {
+        // Ranks in the style of cl100k_base, where byte tokens are not in byte order.
+        let mut content = String::new();
+        let mut tokens: Vec<Vec<u8>> = (0..=255u8).rev().map(|byte| vec![byte]).collect();
+        tokens.extend([b"ab".to_vec(), b"cd".to_vec(), b"abcd".to_vec(), b"bc".to_vec()]);
+        for (rank, token) in tokens.iter().enumerate() {
+            content.push_str(&format!("{} {}\n", base64_encode(token), rank));
+        }
+
+        let tokenizer = Tokenizer::from_tiktoken_ranks(&content)?;
+        assert_eq!(tokenizer.vocab[&(b'a' as u32)], b"a");
+        assert_eq!(tokenizer.merges[&(97, 98)], 256);
+        assert_eq!(tokenizer.merges[&(256, 257)], 258);
+        assert_eq!(tokenizer.vocab[&259], b"bc");
+        assert_eq!(tokenizer.encode_bytes(b"abcde"), vec![258, 101]);
+
+        assert!(Tokenizer::from_tiktoken_ranks("YQ== 0\nYQ== 1").is_err());
+        assert!(Tokenizer::from_tiktoken_ranks("YWJj 300").is_err());
+        assert!(Tokenizer::from_tiktoken_ranks("not base64!").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_tiktoken_ranks_rejects_missing_parts() {
+        // "ab" is ranked before the bytes it is made of.
+        let Err(error) = Tokenizer::from_tiktoken_ranks("YWI= 0\nYQ== 1\nYg== 2\n") else {
+            panic!("expected an error");
+        };
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "rank 0 cannot be built by merging two lower ranks");
+    }
+}
@@ -0,0 +1,252 @@
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{self, BufWriter, Write};
+
+use regex::bytes::Regex;
+
+/// A rule describing tokens that training must never create.
+#[derive(Clone, Debug)]
+pub enum Blocked {
+    /// Block every token containing these bytes.
+    Bytes(Vec<u8>),
+    /// Block every token whose bytes contain a match of this pattern. Anchor the pattern with
+    /// `^...$` to only block exact tokens.
+    Pattern(Regex),
+}
+
+impl Blocked {
+    /// Returns whether `token` is blocked by this rule.
+    pub fn matches(&self, token: &[u8]) -> bool {
+        match self {
+            Blocked::Bytes(needle) => {
+                needle.is_empty() || token.windows(needle.len()).any(|window| window == needle)
+            }
+            Blocked::Pattern(pattern) => pattern.is_match(token),
+        }
+    }
+}
+
+impl PartialEq for Blocked {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Blocked::Bytes(a), Blocked::Bytes(b)) => a == b,
+            (Blocked::Pattern(a), Blocked::Pattern(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Blocked {}
+
+/// Options controlling a training run.
+///
+/// Besides the vocab size, the options can forbid merges whose resulting token would be
+/// undesirable. Forbidden merges are skipped and training continues with the most frequent
+/// allowed pair; if no pair is allowed, training stops early. Training also stops early once a
+/// merge gains less than `min_gain`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainOptions {
+    /// Size of the vocabulary, including the 256 byte tokens.
+    pub vocab_size: u32,
+    /// Print every merge as it is learned. Ignored without the `std` feature.
+    pub verbose: bool,
+    /// Longest token, in bytes, that training may create.
+    pub max_token_len: Option<usize>,
+    /// Forbid tokens in which whitespace follows a non-whitespace character, so that tokens never
+    /// span more than one word. Leading whitespace (" the") and pure whitespace runs are fine.
+    /// Whitespace is Unicode whitespace such as U+00A0 or U+3000; bytes that are not valid UTF-8
+    /// count as non-whitespace.
+    pub whitespace_guard: bool,
+    /// Tokens that must never be learned. Since a merge can only create a token from smaller
+    /// ones, blocking every token that contains a banned sequence guarantees the sequence never
+    /// ends up inside a single token.
+    pub blocklist: Vec<Blocked>,
+    /// Stop training before the first merge that shrinks the corpus by less than this fraction
+    /// of its tokens, see [`MergeStep::gain`].
+    pub min_gain: Option<f64>,
+    /// Record a [`MergeStep`] for every merge in the tokenizer's `growth_curve`.
+    pub record_curve: bool,
+}
+
+impl TrainOptions {
+    pub fn new(vocab_size: u32) -> Self {
+        Self {
+            vocab_size,
+            verbose: false,
+            max_token_len: None,
+            whitespace_guard: false,
+            blocklist: Vec::new(),
+            min_gain: None,
+            record_curve: false,
+        }
+    }
+
+    /// Returns whether a merge with the given [`MergeStep::gain`] is worth keeping.
+    pub fn keeps_gain(&self, gain: f64) -> bool {
+        self.min_gain.is_none_or(|min_gain| gain >= min_gain)
+    }
+
+    /// Returns whether training may create a token with the given bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rbpe::train::TrainOptions;
+    ///
+    /// let options = TrainOptions { whitespace_guard: true, ..TrainOptions::new(512) };
+    /// assert!(options.allows(b" the"));
+    /// assert!(!options.allows(b"the "));
+    /// assert!(!options.allows(b" the cat"));
+    /// ```
+    pub fn allows(&self, token: &[u8]) -> bool {
+        if self.max_token_len.is_some_and(|max_len| token.len() > max_len) {
+            return false;
+        }
+        if self.whitespace_guard && crosses_whitespace_boundary(token) {
+            return false;
+        }
+        !self.blocklist.iter().any(|blocked| blocked.matches(token))
+    }
+}
+
+/// Returns whether a whitespace character follows a non-whitespace character or byte in `token`.
+fn crosses_whitespace_boundary(token: &[u8]) -> bool {
+    let mut after_whitespace = true;
+    for chunk in token.utf8_chunks() {
+        for ch in chunk.valid().chars() {
+            if ch.is_whitespace() && !after_whitespace {
+                return true;
+            }
+            after_whitespace = ch.is_whitespace();
+        }
+        if !chunk.invalid().is_empty() {
+            after_whitespace = false;
+        }
+    }
+    false
+}
+
+/// One point of the vocab growth curve: what a single merge did to the training corpus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MergeStep {
+    /// Id of the merged token.
+    pub id: u32,
+    /// The merged pair.
+    pub pair: (u32, u32),
+    /// How often the pair occurred before the merge.
+    pub frequency: u64,
+    /// Number of tokens in the corpus after the merge.
+    pub tokens: usize,
+    /// Fraction of the corpus tokens that the merge removed. Gains shrink as the vocab grows,
+    /// and a flat tail means extra vocab buys little compression.
+    pub gain: f64,
+}
+
+impl MergeStep {
+    /// Describes merging `pair` into `id` when it turned `tokens_before` tokens into
+    /// `tokens_after`.
+    pub fn new(
+        id: u32,
+        pair: (u32, u32),
+        frequency: u64,
+        tokens_before: usize,
+        tokens_after: usize,
+    ) -> Self {
+        let gain = if tokens_before == 0 {
+            0.0
+        } else {
+            (tokens_before - tokens_after) as f64 / tokens_before as f64
+        };
+        MergeStep { id, pair, frequency, tokens: tokens_after, gain }
+    }
+}
+
+/// Writes a growth curve as CSV with the columns `id`, `pair`, `frequency`, `tokens` and `gain`.
+#[cfg(feature = "std")]
+pub fn write_growth_curve_csv(path: &str, curve: &[MergeStep]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "id,pair,frequency,tokens,gain")?;
+    for step in curve {
+        writeln!(
+            file,
+            "{},{} {},{},{},{}",
+            step.id, step.pair.0, step.pair.1, step.frequency, step.tokens, step.gain
+        )?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_everything_by_default() {
+        let options = TrainOptions::new(512);
+        for token in [&b"a"[..], b"the cat sat", b"  \n", b"x\n"] {
+            assert!(options.allows(token));
+        }
+    }
+
+    #[test]
+    fn test_max_token_len() {
+        let options = TrainOptions { max_token_len: Some(3), ..TrainOptions::new(512) };
+        assert!(options.allows(b"abc"));
+        assert!(!options.allows(b"abcd"));
+    }
+
+    #[test]
+    fn test_whitespace_guard() {
+        let options = TrainOptions { whitespace_guard: true, ..TrainOptions::new(512) };
+        assert!(options.allows(b" hello"));
+        assert!(options.allows(b"\n\n  "));
+        assert!(options.allows(b"hello"));
+        assert!(!options.allows(b"hello\n"));
+        assert!(!options.allows(b"a b"));
+
+        assert!(!options.allows("a\u{a0}b".as_bytes()));
+        assert!(!options.allows("東京\u{3000}".as_bytes()));
+        assert!(options.allows("\u{3000}東京".as_bytes()));
+        // A partial character is not whitespace yet, nor is a stray byte before one.
+        assert!(options.allows(b"a\xc2"));
+        assert!(!options.allows(b"\xff\xc2\xa0"));
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let options = TrainOptions {
+            blocklist: vec![
+                Blocked::Bytes(b"secret".to_vec()),
+                Blocked::Pattern(Regex::new(r"^[0-9]{4}$").unwrap()),
+            ],
+            ..TrainOptions::new(512)
+        };
+        assert!(options.allows(b"secre"));
+        assert!(!options.allows(b"secret"));
+        assert!(!options.allows(b" secrets"));
+        assert!(options.allows(b"123"));
+        assert!(!options.allows(b"1234"));
+        assert!(options.allows(b"12345"));
+    }
+
+    #[test]
+    fn test_min_gain() {
+        assert!(TrainOptions::new(512).keeps_gain(0.0));
+        let options = TrainOptions { min_gain: Some(0.01), ..TrainOptions::new(512) };
+        assert!(options.keeps_gain(0.01));
+        assert!(!options.keeps_gain(0.009));
+
+        let step = MergeStep::new(256, (97, 98), 30, 200, 180);
+        assert_eq!(step.tokens, 180);
+        assert_eq!(step.gain, 0.1);
+    }
+
+    #[test]
+    fn test_blocked_eq() {
+        let pattern = || Blocked::Pattern(Regex::new("a+").unwrap());
+        assert_eq!(pattern(), pattern());
+        assert_ne!(pattern(), Blocked::Bytes(b"a+".to_vec()));
+    }
+}
@@ -1,4 +1,8 @@
-use std::collections::HashMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::HashMap;
 
 /// Calculates the statistics of consecutive pairs of IDs in the given slice.
 /// Returns a HashMap where the keys are pairs of IDs and the values are the number of times the pair appears.
@@ -10,6 +14,8 @@ use std::collections::HashMap;
 /// # Examples
 ///
 /// ```
+/// use rbpe::util::get_stats;
+///
 /// let ids = vec![1, 2, 1, 2, 3, 1, 2];
 /// let stats = get_stats(&ids);
 /// assert_eq!(stats.get(&(1, 2)), Some(&3)); // Appears 3 times
@@ -19,7 +25,7 @@ use std::collections::HashMap;
 /// assert_eq!(stats.len(), 4); // Only 4 unique pairs
 /// ```
 pub fn get_stats(ids: &[u32]) -> HashMap<(u32, u32), u32> {
-    let mut counts = HashMap::new();
+    let mut counts = HashMap::default();
     for window in ids.windows(2) {
         *counts.entry((window[0], window[1])).or_insert(0) += 1;
     }
@@ -38,25 +44,44 @@ pub fn get_stats(ids: &[u32]) -> HashMap<(u32, u32), u32> {
 /// # Examples
 ///
 /// ```
+/// use rbpe::util::merge;
+///
 /// let ids = vec![1, 2, 1, 2, 3, 1, 2];
 /// let pair = (1, 2);
 /// let new_id = 256;
 /// let merged_ids = merge(ids, pair, new_id);
 /// assert_eq!(merged_ids, vec![256, 256, 3, 256]); // Pair (1, 2) replaced by 256
 /// ```
-pub fn merge(ids: Vec<u32>, pair: (u32, u32), idx: u32) -> Vec<u32> {
-    let mut new_ids = Vec::new();
-    let mut i = 0;
-    while i < ids.len() {
-        if i + 1 < ids.len() && ids[i] == pair.0 && ids[i + 1] == pair.1 {
-            new_ids.push(idx);
-            i += 2;
+pub fn merge(mut ids: Vec<u32>, pair: (u32, u32), idx: u32) -> Vec<u32> {
+    merge_in_place(&mut ids, pair, idx);
+    ids
+}
+
+/// Like [`merge`], but replaces the pair in `ids` itself instead of allocating a new vector.
+///
+/// # Examples
+///
+/// ```
+/// use rbpe::util::merge_in_place;
+///
+/// let mut ids = vec![1, 2, 1, 2, 3, 1, 2];
+/// merge_in_place(&mut ids, (1, 2), 256);
+/// assert_eq!(ids, vec![256, 256, 3, 256]);
+/// ```
+pub fn merge_in_place(ids: &mut Vec<u32>, pair: (u32, u32), idx: u32) {
+    let mut read = 0;
+    let mut write = 0;
+    while read < ids.len() {
+        if read + 1 < ids.len() && ids[read] == pair.0 && ids[read + 1] == pair.1 {
+            ids[write] = idx;
+            read += 2;
         } else {
-            new_ids.push(ids[i]);
-            i += 1;
+            ids[write] = ids[read];
+            read += 1;
         }
+        write += 1;
     }
-    new_ids
+    ids.truncate(write);
 }
 
 /// Replaces control characters in the given string with their Unicode escape sequences.
@@ -69,6 +94,8 @@ pub fn merge(ids: Vec<u32>, pair: (u32, u32), idx: u32) -> Vec<u32> {
 /// # Examples
 ///
 /// ```
+/// use rbpe::util::replace_control_characters;
+///
 /// let input = "\u{0007}Hello, \u{0009}world!\u{000A}";
 /// let expected = "\\u0007Hello, \\u0009world!\\u000a";
 /// assert_eq!(replace_control_characters(input), expected);
@@ -89,6 +116,8 @@ pub fn replace_control_characters(s: &str) -> String {
 /// # Examples
 ///
 /// ```
+/// use rbpe::util::render_token;
+///
 /// let token = &[0x00, 0x1F, 0x20, 0x7F];
 /// assert_eq!(render_token(token), "\\x00\\x1f \\x7f");
 /// ```
@@ -103,10 +132,137 @@ pub fn render_token(token: &[u8]) -> String {
     result
 }
 
+/// Quotes a field for a CSV file if it contains a separator, quote or line break.
+/// Returns the field ready to be written.
+///
+/// # Arguments
+///
+/// * `field` - The raw field value.
+///
+/// # Examples
+///
+/// ```
+/// use rbpe::util::csv_field;
+///
+/// assert_eq!(csv_field("plain"), "plain");
+/// assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+/// ```
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes using the standard base64 alphabet with padding.
+/// Returns the encoded string.
+///
+/// # Arguments
+///
+/// * `bytes` - The bytes to encode.
+///
+/// # Examples
+///
+/// ```
+/// use rbpe::util::base64_encode;
+///
+/// assert_eq!(base64_encode(b"hello"), "aGVsbG8=");
+/// ```
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Decodes standard base64 with optional padding.
+/// Returns `None` if the input contains characters outside the alphabet or has an invalid
+/// length.
+///
+/// # Examples
+///
+/// ```
+/// use rbpe::util::base64_decode;
+///
+/// assert_eq!(base64_decode("aGVsbG8="), Some(b"hello".to_vec()));
+/// assert_eq!(base64_decode("aGVsbG8"), Some(b"hello".to_vec()));
+/// assert_eq!(base64_decode("a!=="), None);
+/// ```
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    if text.len() % 4 == 1 {
+        return None;
+    }
+    let mut result = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.as_bytes().chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+            n |= value << (18 - 6 * i);
+        }
+        result.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(result)
+}
+
+/// The FxHash function from rustc, used for the maps of `no_std` builds, which have no source
+/// of random seeds. It is fast on the small integer keys of the merges, but, being unseeded,
+/// not resistant to inputs crafted to collide.
+#[cfg(not(feature = "std"))]
+#[derive(Default)]
+pub struct FxHasher {
+    hash: u64,
+}
+
+#[cfg(not(feature = "std"))]
+impl core::hash::Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            self.write_u64(u64::from_le_bytes(word.try_into().unwrap()));
+        }
+        for &byte in words.remainder() {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ value).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::{get_stats, merge, render_token, replace_control_characters};
+    use super::{
+        base64_decode, base64_encode, csv_field, get_stats, merge, render_token,
+        replace_control_characters,
+    };
 
     #[test]
     fn test_get_stats() {
@@ -169,4 +325,33 @@ mod tests {
         let token: &[u8] = &[];
         assert_eq!(render_token(token), "");
     }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(&[0xff, 0xfe, 0x00]), "//4A");
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        for bytes in [&b""[..], b"f", b"fo", b"foo", b"foobar", &[0xff, 0xfe, 0x00]] {
+            assert_eq!(base64_decode(&base64_encode(bytes)).as_deref(), Some(bytes));
+        }
+        assert_eq!(base64_decode("Zg"), Some(b"f".to_vec()));
+        assert_eq!(base64_decode("Zm9vY"), None);
+        assert_eq!(base64_decode("Zm 9v"), None);
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("hello world"), "hello world");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
 }